@@ -1,6 +1,6 @@
 use aarch64_cpu::asm; // aarch64_cpu crate for asm
 
-pub use asm::nop;
+pub use asm::{nop, wfi};
 
 #[inline(always)]
 pub fn wait_forever() -> ! {
@@ -8,3 +8,35 @@ pub fn wait_forever() -> ! {
         asm::wfe();
     }
 }
+
+/// Fill `addrs` with the return addresses of the current call chain, innermost first, by walking
+/// the frame records. Returns how many were found.
+///
+/// Only frame pointers inside `stack` are followed. Without frame pointers (`-C
+/// force-frame-pointers=yes`) the walk stops early or finds nothing.
+#[cfg(feature = "debug_heap")]
+#[inline(always)]
+pub fn return_addresses(stack: core::ops::RangeInclusive<usize>, addrs: &mut [usize]) -> usize {
+    let mut fp: usize;
+    unsafe { core::arch::asm!("mov {}, x29", out(reg) fp, options(nomem, nostack)) };
+
+    let mut found = 0;
+    while found < addrs.len() && fp % 16 == 0 && stack.contains(&fp) && stack.contains(&(fp + 15)) {
+        // a frame record is the caller's frame pointer followed by the return address
+        let (next_fp, lr) = unsafe { (*(fp as *const usize), *((fp + 8) as *const usize)) };
+        if lr == 0 {
+            break;
+        }
+
+        addrs[found] = lr;
+        found += 1;
+
+        // the stack grows down, callers' records sit above
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+
+    found
+}
@@ -1,5 +1,6 @@
 //! Architectural boot code.
 
+use crate::{cpu, memory};
 use aarch64_cpu::{asm, registers::*};
 use core::arch::global_asm;
 use tock_registers::interfaces::Writeable;
@@ -11,7 +12,7 @@ global_asm!(
 );
 
 #[inline(always)]
-unsafe fn prep_el2_to_el1_trans(phys_boot_core_stack_end_exclusive_addr: u64) {
+unsafe fn prep_el2_to_el1_trans(virt_stack_end_exclusive_addr: u64, virt_entry_addr: u64) {
     // allow timers for EL1
     CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
 
@@ -21,29 +22,38 @@ unsafe fn prep_el2_to_el1_trans(phys_boot_core_stack_end_exclusive_addr: u64) {
     // set EL1 to op in Aarch64 mode
     HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
 
+    // start EL1 with everything masked, the kernel unmasks IRQs once its handlers are installed
     SPSR_EL2.write(
-        SPSR_EL2::D::Unmasked
-            + SPSR_EL2::A::Unmasked
-            + SPSR_EL2::I::Unmasked
-            + SPSR_EL2::F::Unmasked
+        SPSR_EL2::D::Masked
+            + SPSR_EL2::A::Masked
+            + SPSR_EL2::I::Masked
+            + SPSR_EL2::F::Masked
             + SPSR_EL2::M::EL1h,
     );
 
     // load kernel init addr here
-    // when going down levels it goes to this addr
-    ELR_EL2.set(crate::kernel_init as *const () as u64);
+    // when going down levels it goes to this addr, in the higher half
+    ELR_EL2.set(virt_entry_addr);
 
     // setup el1 stack
-    SP_EL1.set(phys_boot_core_stack_end_exclusive_addr);
+    SP_EL1.set(virt_stack_end_exclusive_addr);
 }
 
 // The Rust entry of the `kernel` binary.
-// function is called from the assembly `_start` function.
-// x0 contains the top of stack addr from the asm code
-// x0 is the 1st arg reg in ARM
+// function is called from the assembly `_start` function, by every core.
+// x0 contains the virtual top of the executing core's stack, x1 the virtual address of
+// `kernel_init()` or `kernel_init_secondary()`.
+//
+// Still runs at the physical load address with the MMU off, so nothing in here may use absolute
+// addresses or panic.
 #[no_mangle]
-pub unsafe fn _start_rust(phys_boot_core_stack_end_exclusive_addr: u64) -> ! {
-    prep_el2_to_el1_trans(phys_boot_core_stack_end_exclusive_addr);
+pub unsafe fn _start_rust(virt_stack_end_exclusive_addr: u64, virt_entry_addr: u64) -> ! {
+    // map the kernel into the higher half, EL1 starts out with translation on
+    if !memory::mmu::enable_mmu_and_caching_early() {
+        cpu::wait_forever();
+    }
+
+    prep_el2_to_el1_trans(virt_stack_end_exclusive_addr, virt_entry_addr);
 
     // Use `eret` to "return" to EL1. This results in execution of kernel_init() in EL1.
     asm::eret();
@@ -9,6 +9,18 @@
 	add	\register, \register, #:lo12:\symbol	// load low part into reg 
 .endm
 
+// 	Load the address of a symbol into a register, absolute.
+// 		The kernel is linked in the higher half, so this is the symbol's virtual address.
+.macro ADR_ABS register, symbol
+	movz	\register, #:abs_g3:\symbol
+	movk	\register, #:abs_g2_nc:\symbol
+	movk	\register, #:abs_g1_nc:\symbol
+	movk	\register, #:abs_g0_nc:\symbol
+.endm
+
+// Everything up to the jump into EL1 runs with the MMU off at the physical load address. Only
+// PC-relative addressing (ADR_REL, ldr literal) yields usable addresses here.
+
 // define entry
 .section .text._start
 
@@ -20,13 +32,14 @@ _start:
 	b.ne	.L_parking_loop						// if not priv level 2 then spin
 
 
-	// stop all the other cores except 0	
+	// only the boot core sets up memory, the secondary cores (released later from the firmware's
+	// spin table by the kernel) go straight to their stack
 	// MPIDR_EL1 has info about the current CPU core(core ID, cluster ID)
 	mrs		x0, MPIDR_EL1						// load core info from sys reg into x0
 	and		x0, x0, {CONST_CORE_ID_MASK}		// mask for core id 
 	ldr		x1, BOOT_CORE_ID      				// load core_id in x1 for comparison
 	cmp		x0, x1								
-	b.ne	.L_parking_loop						// if not core 0 park
+	b.ne	.L_prepare_stack					// secondary core, skip the boot core init
 
 	// Initialize DRAM start and end
 	ADR_REL	x0, __bss_start					
@@ -39,10 +52,6 @@ _start:
 	b		.L_bss_init_loop						// keep zeroing out bss
 
 .L_prepare_rust:
-	// Set the stack pointer.
-	ADR_REL	x0, __boot_core_stack_end_exclusive	// x0 has top addr
-	mov	sp, x0									// copy x0 to sp reg
-
 	// Read cpu's timer info
 	ADR_REL x1, ARCH_TIMER_COUNTER_FREQUENCY 	// load addr for timer info (inside kernel) into x1
 	mrs 	x2, CNTFRQ_EL0						// load the timer info into x2
@@ -50,7 +59,33 @@ _start:
 	b.eq	.L_parking_loop						// if CNT FREQ is 0 sumting wong
 	str		w2, [x1]							// save the info in the var
 
-	b	_start_rust								// start the rust code
+.L_prepare_stack:
+	// Set the stack pointer. Every core has its own slot of guard page + stack, core n's stack
+	// ends at __core_stacks_start + (n + 1) * slot size
+	ADR_REL	x1, __core_stacks_start
+	ADR_REL	x2, __core_stacks_end_exclusive
+	sub		x2, x2, x1
+	lsr		x2, x2, #2							// size of a single slot, 4 cores
+	mrs		x3, MPIDR_EL1
+	and		x3, x3, {CONST_CORE_ID_MASK}
+	add		x0, x3, #1
+	madd	x0, x0, x2, x1						// x0 has the physical top addr
+	mov		sp, x0								// the stack for the EL2 part, MMU is still off
+
+	// the same stack at its virtual address, for EL1
+	ADR_ABS	x2, __core_stacks_start
+	sub		x2, x2, x1							// virtual - physical offset
+	add		x0, x0, x2
+
+	// the virtual entry point in EL1, per core
+	ADR_ABS	x1, kernel_init
+	ldr		x2, BOOT_CORE_ID
+	cmp		x3, x2
+	b.eq	.L_start_rust
+	ADR_ABS	x1, kernel_init_secondary
+
+.L_start_rust:
+	b	_start_rust								// start the rust code, x0 and x1 are the args
 	
 .L_parking_loop:
 	wfe											// sleepy time
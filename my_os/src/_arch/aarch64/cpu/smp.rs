@@ -0,0 +1,52 @@
+//! Architectural symmetric multiprocessing.
+
+use crate::memory;
+use aarch64_cpu::{asm, asm::barrier, registers::*};
+use core::arch::asm;
+use tock_registers::interfaces::Readable;
+
+extern "C" {
+    fn _start();
+}
+
+/// Return the executing core's id.
+#[inline(always)]
+pub fn core_id<T>() -> T
+where
+    T: From<u8>,
+{
+    const CORE_MASK: u64 = 0b11;
+
+    T::from((MPIDR_EL1.get() & CORE_MASK) as u8)
+}
+
+/// Send a core parked in the firmware's spin table to `_start`.
+///
+/// # Safety
+///
+/// - `release_addr` must be the virtual address of the spin table entry of a core that is still
+///   parked.
+pub unsafe fn release_core(release_addr: usize) {
+    // the core comes up with the MMU off, it needs the physical load address
+    let entry = memory::virt_to_phys(_start as usize) as u64;
+
+    core::ptr::write_volatile(release_addr as *mut u64, entry);
+
+    // the parked core polls with its caches off, so the write has to make it out to memory
+    asm!("dc civac, {}", in(reg) release_addr, options(nostack));
+    barrier::dsb(barrier::SY);
+
+    asm::sev();
+}
+
+/// Wake up all cores sleeping in `wait_for_event()`.
+#[inline(always)]
+pub fn send_event() {
+    asm::sev();
+}
+
+/// Sleep until an event (or an IRQ) comes in.
+#[inline(always)]
+pub fn wait_for_event() {
+    asm::wfe();
+}
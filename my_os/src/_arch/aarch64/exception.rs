@@ -1,8 +1,348 @@
 //! Architectural synchronous and asynchronous exception handling.
 
-use crate::exception::PrivilegeLevel;
-use aarch64_cpu::registers::*;
-use tock_registers::interfaces::Readable;
+use crate::{
+    bsp,
+    exception::{self, PrivilegeLevel},
+};
+use aarch64_cpu::{asm::barrier, registers::*};
+use core::{arch::global_asm, cell::UnsafeCell, fmt};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    registers::InMemoryRegister,
+};
+
+// the vector table and the context save/restore code
+global_asm!(
+    include_str!("exception.s"),
+    CONST_CORE_STACK_SHIFT = const bsp::memory::CORE_STACK_SHIFT
+);
+
+/// Wrapper structs for memory copies of registers.
+#[repr(transparent)]
+struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
+#[repr(transparent)]
+struct EsrEL1(InMemoryRegister<u64, ESR_EL1::Register>);
+
+/// The exception context as it is stored on the stack on exception entry.
+/// NOTE: layout has to match `CALL_WITH_CONTEXT` in exception.s
+#[repr(C)]
+struct ExceptionContext {
+    /// General Purpose Registers x0 - x29.
+    gpr: [u64; 30],
+
+    /// The link register, aka x30.
+    lr: u64,
+
+    /// Exception link register. The program counter at the time the exception happened.
+    elr_el1: u64,
+
+    /// Saved program status.
+    spsr_el1: SpsrEL1,
+
+    /// Exception syndrome register.
+    esr_el1: EsrEL1,
+
+    /// Fault address register. Only valid for aborts and alignment faults.
+    far_el1: u64,
+
+    /// Keep the stack 16 byte aligned.
+    _padding: u64,
+}
+
+/// Prints verbose information about the exception and then panics.
+fn default_exception_handler(exc: &ExceptionContext) {
+    panic!(
+        "CPU Exception!\n\n\
+        {}",
+        exc
+    );
+}
+
+//--------------------------------------------------------------------------------------------------
+// Current, EL0
+//--------------------------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn current_el0_synchronous(_e: &mut ExceptionContext) {
+    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+}
+
+#[no_mangle]
+extern "C" fn current_el0_irq(_e: &mut ExceptionContext) {
+    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+}
+
+#[no_mangle]
+extern "C" fn current_el0_serror(_e: &mut ExceptionContext) {
+    panic!("Should not be here. Use of SP_EL0 in EL1 is not supported.")
+}
+
+//--------------------------------------------------------------------------------------------------
+// Current, ELx
+//--------------------------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    if let Some(core) = e.stack_overflow_core() {
+        panic!("kernel stack overflow on core {}\n\n{}", core, e);
+    }
+
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+    let token = unsafe { &exception::asynchronous::IRQContext::new() };
+    exception::asynchronous::irq_manager().handle_pending_irqs(token);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Lower, AArch64
+//--------------------------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Lower, AArch32
+//--------------------------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn lower_aarch32_synchronous(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_irq(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
+    default_exception_handler(e);
+}
+
+//------------------------------------------------------------------------------
+// Misc
+//------------------------------------------------------------------------------
+
+/// Human readable SPSR_EL1.
+#[rustfmt::skip]
+impl fmt::Display for SpsrEL1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Raw value.
+        writeln!(f, "SPSR_EL1: {:#010x}", self.0.get())?;
+
+        let to_flag_str = |x| -> _ {
+            if x { "Set" } else { "Not set" }
+         };
+
+        writeln!(f, "      Flags:")?;
+        writeln!(f, "            Negative (N): {}", to_flag_str(self.0.is_set(SPSR_EL1::N)))?;
+        writeln!(f, "            Zero     (Z): {}", to_flag_str(self.0.is_set(SPSR_EL1::Z)))?;
+        writeln!(f, "            Carry    (C): {}", to_flag_str(self.0.is_set(SPSR_EL1::C)))?;
+        writeln!(f, "            Overflow (V): {}", to_flag_str(self.0.is_set(SPSR_EL1::V)))?;
+
+        let to_mask_str = |x| -> _ {
+            if x { "Masked" } else { "Unmasked" }
+        };
+
+        writeln!(f, "      Exception handling state:")?;
+        writeln!(f, "            Debug  (D): {}", to_mask_str(self.0.is_set(SPSR_EL1::D)))?;
+        writeln!(f, "            SError (A): {}", to_mask_str(self.0.is_set(SPSR_EL1::A)))?;
+        writeln!(f, "            IRQ    (I): {}", to_mask_str(self.0.is_set(SPSR_EL1::I)))?;
+        writeln!(f, "            FIQ    (F): {}", to_mask_str(self.0.is_set(SPSR_EL1::F)))?;
+
+        write!(f, "      Illegal Execution State (IL): {}",
+            to_flag_str(self.0.is_set(SPSR_EL1::IL))
+        )
+    }
+}
+
+/// Decode the fault status code of a data or instruction abort (ISS[5:0]).
+fn fault_status_str(fsc: u64) -> &'static str {
+    match fsc {
+        0b00_0000..=0b00_0011 => "Address size fault",
+        0b00_0100..=0b00_0111 => "Translation fault",
+        0b00_1001..=0b00_1011 => "Access flag fault",
+        0b00_1101..=0b00_1111 => "Permission fault",
+        0b01_0000 => "Synchronous External abort",
+        0b10_0001 => "Alignment fault",
+        0b11_0000 => "TLB conflict abort",
+        _ => "N/A",
+    }
+}
+
+impl EsrEL1 {
+    #[inline(always)]
+    fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
+        self.0.read_as_enum(ESR_EL1::EC)
+    }
+
+    #[inline(always)]
+    fn iss(&self) -> u64 {
+        self.0.read(ESR_EL1::ISS)
+    }
+
+    /// Print the details hidden in the ISS for the exception classes we know about.
+    fn fmt_iss(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ESR_EL1::EC::Value::*;
+
+        let iss = self.iss();
+        match self.exception_class() {
+            Some(DataAbortCurrentEL) | Some(DataAbortLowerEL) => {
+                let access = if iss & (1 << 6) != 0 { "Write" } else { "Read" };
+                let far_valid = iss & (1 << 10) == 0;
+
+                writeln!(f, "            Access:       {}", access)?;
+                writeln!(f, "            FAR valid:    {}", far_valid)?;
+                write!(
+                    f,
+                    "            Fault status: {:#04x} - {}",
+                    iss & 0x3F,
+                    fault_status_str(iss & 0x3F)
+                )
+            }
+            Some(InstrAbortCurrentEL) | Some(InstrAbortLowerEL) => write!(
+                f,
+                "            Fault status: {:#04x} - {}",
+                iss & 0x3F,
+                fault_status_str(iss & 0x3F)
+            ),
+            Some(SVC64) => write!(f, "            SVC immediate: {:#06x}", iss & 0xFFFF),
+            Some(Brk64) => write!(f, "            BRK comment:   {:#06x}", iss & 0xFFFF),
+            _ => write!(f, "            No further decoding"),
+        }
+    }
+}
+
+/// Human readable ESR_EL1.
+#[rustfmt::skip]
+impl fmt::Display for EsrEL1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ESR_EL1::EC::Value::*;
+
+        // Raw print of whole register.
+        writeln!(f, "ESR_EL1: {:#010x}", self.0.get())?;
+
+        // Raw print of exception class.
+        write!(f, "      Exception Class         (EC) : {:#x}", self.0.read(ESR_EL1::EC))?;
+
+        // Exception class.
+        let ec_translation = match self.exception_class() {
+            Some(Unknown) => "Unknown reason",
+            Some(TrappedWFIorWFE) => "Trapped WFI or WFE",
+            Some(IllegalExecutionState) => "Illegal Execution State",
+            Some(SVC64) => "Supervisor Call (SVC)",
+            Some(HVC64) => "Hypervisor Call (HVC)",
+            Some(SMC64) => "Secure Monitor Call (SMC)",
+            Some(TrappedMsrMrs) => "Trapped MSR, MRS or system instruction",
+            Some(InstrAbortLowerEL) => "Instruction Abort, lower EL",
+            Some(InstrAbortCurrentEL) => "Instruction Abort, current EL",
+            Some(PCAlignmentFault) => "Misaligned PC",
+            Some(DataAbortLowerEL) => "Data Abort, lower EL",
+            Some(DataAbortCurrentEL) => "Data Abort, current EL",
+            Some(SPAlignmentFault) => "Misaligned SP",
+            Some(TrappedFP64) => "Trapped floating-point exception",
+            Some(SError) => "SError interrupt",
+            Some(Brk64) => "Breakpoint (BRK)",
+            _ => "N/A",
+        };
+        writeln!(f, " - {}", ec_translation)?;
+
+        // Raw print of instruction specific syndrome.
+        writeln!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.iss())?;
+
+        self.fmt_iss(f)
+    }
+}
+
+impl ExceptionContext {
+    #[inline(always)]
+    fn exception_class(&self) -> Option<ESR_EL1::EC::Value> {
+        self.esr_el1.exception_class()
+    }
+
+    /// If this is a data abort in the guard below one of the kernel stacks, return the core the
+    /// stack belongs to.
+    fn stack_overflow_core(&self) -> Option<usize> {
+        use ESR_EL1::EC::Value::*;
+
+        if !matches!(self.exception_class(), Some(DataAbortCurrentEL)) {
+            return None;
+        }
+
+        // FAR not valid
+        if self.esr_el1.iss() & (1 << 10) != 0 {
+            return None;
+        }
+
+        bsp::memory::core_of_stack_guard(self.far_el1 as usize)
+    }
+
+    /// FAR_EL1 only holds something useful for aborts and alignment faults.
+    #[inline(always)]
+    fn fault_address_valid(&self) -> bool {
+        use ESR_EL1::EC::Value::*;
+
+        match self.exception_class() {
+            None => false,
+            Some(ec) => matches!(
+                ec,
+                InstrAbortLowerEL
+                    | InstrAbortCurrentEL
+                    | PCAlignmentFault
+                    | DataAbortLowerEL
+                    | DataAbortCurrentEL
+                    | SPAlignmentFault
+            ),
+        }
+    }
+}
+
+/// Human readable print of the exception context.
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.esr_el1)?;
+
+        if self.fault_address_valid() {
+            writeln!(f, "FAR_EL1: {:#018x}", self.far_el1)?;
+        }
+
+        writeln!(f, "{}", self.spsr_el1)?;
+        writeln!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
+        writeln!(f)?;
+        writeln!(f, "General purpose register:")?;
+
+        #[rustfmt::skip]
+        let alternating = |x| -> _ {
+            if x % 2 == 0 { "   " } else { "\n" }
+        };
+
+        // Print two registers per line.
+        for (i, reg) in self.gpr.iter().enumerate() {
+            write!(f, "      x{: <2}: {: >#018x}{}", i, reg, alternating(i))?;
+        }
+        write!(f, "      lr : {:#018x}", self.lr)
+    }
+}
 
 pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     let el = CurrentEL.read_as_enum(CurrentEL::EL);
@@ -13,3 +353,23 @@ pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
         _ => (PrivilegeLevel::Unknown, "Unknown"),
     }
 }
+
+/// Init exception handling by setting the exception vector base address register.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - The vector table and the symbol `__exception_vector_start` from the linker script must
+///   adhere to the alignment and size constraints demanded by the ARMv8-A Architecture Reference
+///   Manual.
+pub unsafe fn handling_init() {
+    // Provided by exception.s.
+    extern "Rust" {
+        static __exception_vector_start: UnsafeCell<()>;
+    }
+
+    VBAR_EL1.set(__exception_vector_start.get() as u64);
+
+    // Force VBAR update to complete before next instruction.
+    barrier::isb(barrier::SY);
+}
@@ -0,0 +1,175 @@
+#! Exception vector table
+
+// Save the full register context on the stack and call the Rust handler with a pointer to it.
+//
+// Context layout (matches `ExceptionContext` in exception.rs):
+//     [sp, #16 * 0]  .. [sp, #16 * 14]	x0 - x29
+//     [sp, #16 * 15]					lr (x30), ELR_EL1
+//     [sp, #16 * 16]					SPSR_EL1, ESR_EL1
+//     [sp, #16 * 17]					FAR_EL1, padding
+.macro CALL_WITH_CONTEXT handler
+__vector_\handler:
+	// make room on the stack for the context
+	sub		sp,  sp,  #16 * 18
+
+	// save all general purpose registers
+	stp		x0,  x1,  [sp, #16 * 0]
+	stp		x2,  x3,  [sp, #16 * 1]
+	stp		x4,  x5,  [sp, #16 * 2]
+	stp		x6,  x7,  [sp, #16 * 3]
+	stp		x8,  x9,  [sp, #16 * 4]
+	stp		x10, x11, [sp, #16 * 5]
+	stp		x12, x13, [sp, #16 * 6]
+	stp		x14, x15, [sp, #16 * 7]
+	stp		x16, x17, [sp, #16 * 8]
+	stp		x18, x19, [sp, #16 * 9]
+	stp		x20, x21, [sp, #16 * 10]
+	stp		x22, x23, [sp, #16 * 11]
+	stp		x24, x25, [sp, #16 * 12]
+	stp		x26, x27, [sp, #16 * 13]
+	stp		x28, x29, [sp, #16 * 14]
+
+	// save the exception sys regs
+	mrs		x1,  ELR_EL1						// where we came from
+	mrs		x2,  SPSR_EL1						// saved pstate
+	mrs		x3,  ESR_EL1						// why we are here
+	mrs		x4,  FAR_EL1						// faulting addr (only valid for some aborts)
+
+	stp		lr,  x1,  [sp, #16 * 15]
+	stp		x2,  x3,  [sp, #16 * 16]
+	str		x4,       [sp, #16 * 17]
+
+	// x0 is the 1st arg, pass a pointer to the context
+	mov		x0,  sp
+
+	bl		\handler
+
+	// handler returned, restore the context and go back
+	b		__exception_restore_context
+
+.size	__vector_\handler, . - __vector_\handler
+.type	__vector_\handler, function
+.endm
+
+// FIQs are never used, park the core if one shows up
+.macro FIQ_SUSPEND
+1:	wfe
+	b	1b
+.endm
+
+// Check that the context still fits on the stack before saving it, and call the handler.
+//
+// A stack slot is a guard followed by the stack, aligned to its size, so sp is in the guard iff the
+// stack size bit is 0. There is no register free to test sp with, hence the add/sub dance.
+// Otherwise the context would be pushed into the guard, which faults again, forever.
+.macro CHECK_STACK_THEN_CALL handler
+	sub		sp,  sp,  #16 * 18					// where the context would go
+	add		sp,  sp,  x0						// sp = sp + x0
+	sub		x0,  sp,  x0						// x0 = sp
+	tbz		x0,  #{CONST_CORE_STACK_SHIFT}, __stack_overflow
+	sub		x0,  sp,  x0						// x0 = x0
+	sub		sp,  sp,  x0						// sp = sp
+	add		sp,  sp,  #16 * 18
+	b		__vector_\handler
+.endm
+
+.section .text
+
+// VBAR_EL1 needs the table aligned to 2 KiB
+.align 11
+
+// Each of the 16 entries is 0x80 bytes (32 instructions) long.
+__exception_vector_start:
+
+// Current exception level with SP_EL0.
+.org 0x000
+	CALL_WITH_CONTEXT current_el0_synchronous
+.org 0x080
+	CALL_WITH_CONTEXT current_el0_irq
+.org 0x100
+	FIQ_SUSPEND
+.org 0x180
+	CALL_WITH_CONTEXT current_el0_serror
+
+// Current exception level with SP_ELx, x > 0.
+.org 0x200
+	CHECK_STACK_THEN_CALL current_elx_synchronous
+.org 0x280
+	CALL_WITH_CONTEXT current_elx_irq
+.org 0x300
+	FIQ_SUSPEND
+.org 0x380
+	CALL_WITH_CONTEXT current_elx_serror
+
+// Lower exception level, AArch64
+.org 0x400
+	CALL_WITH_CONTEXT lower_aarch64_synchronous
+.org 0x480
+	CALL_WITH_CONTEXT lower_aarch64_irq
+.org 0x500
+	FIQ_SUSPEND
+.org 0x580
+	CALL_WITH_CONTEXT lower_aarch64_serror
+
+// Lower exception level, AArch32
+.org 0x600
+	CALL_WITH_CONTEXT lower_aarch32_synchronous
+.org 0x680
+	CALL_WITH_CONTEXT lower_aarch32_irq
+.org 0x700
+	FIQ_SUSPEND
+.org 0x780
+	CALL_WITH_CONTEXT lower_aarch32_serror
+.org 0x800
+
+.global __exception_vector_start
+
+// Doesn't fit the vector table entry together with the stack check.
+	CALL_WITH_CONTEXT current_elx_synchronous
+
+// The stack overflowed into its guard. Nothing on it is of use anymore, so start over at the top of
+// the same stack and let the handler report it.
+__stack_overflow:
+	sub		x0,  sp,  x0						// x0 = x0
+	sub		sp,  sp,  x0						// sp = sp, inside the guard
+
+	msr		TPIDR_EL1, x0						// free up x0
+	mov		x0,  sp
+	orr		x0,  x0,  #((1 << ({CONST_CORE_STACK_SHIFT} + 1)) - 1)	// last byte of the slot
+	add		x0,  x0,  #1						// the stack's top
+	mov		sp,  x0
+	mrs		x0,  TPIDR_EL1
+
+	b		__vector_current_elx_synchronous
+
+.size	__stack_overflow, . - __stack_overflow
+.type	__stack_overflow, function
+
+__exception_restore_context:
+	ldp		x19, x20, [sp, #16 * 16]			// x19 = SPSR, x20 = ESR (ignored)
+	ldp		lr,  x20, [sp, #16 * 15]			// x20 = ELR
+	msr		SPSR_EL1, x19
+	msr		ELR_EL1,  x20
+
+	ldp		x0,  x1,  [sp, #16 * 0]
+	ldp		x2,  x3,  [sp, #16 * 1]
+	ldp		x4,  x5,  [sp, #16 * 2]
+	ldp		x6,  x7,  [sp, #16 * 3]
+	ldp		x8,  x9,  [sp, #16 * 4]
+	ldp		x10, x11, [sp, #16 * 5]
+	ldp		x12, x13, [sp, #16 * 6]
+	ldp		x14, x15, [sp, #16 * 7]
+	ldp		x16, x17, [sp, #16 * 8]
+	ldp		x18, x19, [sp, #16 * 9]
+	ldp		x20, x21, [sp, #16 * 10]
+	ldp		x22, x23, [sp, #16 * 11]
+	ldp		x24, x25, [sp, #16 * 12]
+	ldp		x26, x27, [sp, #16 * 13]
+	ldp		x28, x29, [sp, #16 * 14]
+
+	add		sp,  sp,  #16 * 18
+
+	eret
+
+.size	__exception_restore_context, . - __exception_restore_context
+.type	__exception_restore_context, function
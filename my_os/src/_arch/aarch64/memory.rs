@@ -0,0 +1,43 @@
+//! Architectural memory management.
+
+use aarch64_cpu::asm::barrier;
+use core::arch::asm;
+
+/// Size of the smallest data cache line in the system.
+#[inline(always)]
+fn dcache_line_size() -> usize {
+    let ctr: usize;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack)) };
+
+    // CTR_EL0.DminLine, log2 of the number of words
+    4 << ((ctr >> 16) & 0xF)
+}
+
+/// Write the data cache lines covering `[start, start + size)` back to memory, so that devices
+/// reading memory directly see the CPU's stores.
+pub fn clean_dcache_range(start: usize, size: usize) {
+    let line = dcache_line_size();
+
+    let mut addr = start & !(line - 1);
+    while addr < start + size {
+        unsafe { asm!("dc cvac, {}", in(reg) addr, options(nostack)) };
+        addr += line;
+    }
+
+    barrier::dsb(barrier::SY);
+}
+
+/// Drop the data cache lines covering `[start, start + size)`, so that the next reads see what a
+/// device wrote to memory. The lines are cleaned on the way out, partly covered lines at the edges
+/// don't lose anything.
+pub fn invalidate_dcache_range(start: usize, size: usize) {
+    let line = dcache_line_size();
+
+    let mut addr = start & !(line - 1);
+    while addr < start + size {
+        unsafe { asm!("dc civac, {}", in(reg) addr, options(nostack)) };
+        addr += line;
+    }
+
+    barrier::dsb(barrier::SY);
+}
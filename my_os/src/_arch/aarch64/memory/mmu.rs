@@ -0,0 +1,239 @@
+//! Memory Management Unit Driver.
+//!
+//! Only 64 KiB granule is supported. The kernel lives in the TTBR1 half, TTBR0 walks stay disabled.
+
+use crate::{
+    bsp, memory,
+    memory::mmu::{
+        translation_table::{BootTranslationTable, KernelTranslationTable},
+        AttributeFields, MMUEnableError, TranslationGranule,
+    },
+};
+use aarch64_cpu::{asm::barrier, registers::*};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
+
+/// Memory Management Unit type.
+struct MemoryManagementUnit;
+
+pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
+pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
+
+// the translation tables only do 64 KiB pages
+#[allow(clippy::assertions_on_constants)]
+const _: () = assert!(bsp::memory::mmu::KernelGranule::SIZE == Granule64KiB::SIZE);
+
+/// Constants for indexing the MAIR_EL1.
+#[allow(dead_code)]
+pub mod mair {
+    pub const DEVICE: u64 = 0;
+    pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
+}
+
+/// The kernel translation tables.
+///
+/// # Safety
+///
+/// - Supposed to land in `.bss`. Therefore, ensure that all initial member values boil down to "0".
+static mut KERNEL_TABLES: KernelTranslationTable = KernelTranslationTable::new();
+
+/// The tables the MMU is switched on with, see `enable_mmu_and_caching_early()`.
+///
+/// # Safety
+///
+/// - Supposed to land in `.bss`, like `KERNEL_TABLES`.
+static mut BOOT_TABLES: BootTranslationTable = BootTranslationTable::new();
+
+// the boot core builds the tables, the secondary cores just use them
+static KERNEL_TABLES_READY: AtomicBool = AtomicBool::new(false);
+
+static MMU: MemoryManagementUnit = MemoryManagementUnit;
+
+impl<const AS_SIZE: usize> memory::mmu::AddressSpace<AS_SIZE> {
+    /// Checks for architectural restrictions.
+    pub const fn arch_address_space_size_sanity_checks() {
+        // Size must be at least one full 512 MiB table.
+        assert!((AS_SIZE % Granule512MiB::SIZE) == 0);
+
+        // Check for 48 bit virtual address size as maximum, which is supported by any ARMv8
+        // version.
+        assert!(AS_SIZE <= (1 << 48));
+    }
+}
+
+impl MemoryManagementUnit {
+    /// Setup function for the MAIR_EL1 register.
+    fn set_up_mair(&self) {
+        // Define the memory types being mapped.
+        MAIR_EL1.write(
+            // Attribute 2 - Non-cacheable normal DRAM.
+            MAIR_EL1::Attr2_Normal_Outer::NonCacheable
+                + MAIR_EL1::Attr2_Normal_Inner::NonCacheable
+                // Attribute 1 - Cacheable normal DRAM.
+                +
+            MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc
+                + MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc
+                // Attribute 0 - Device.
+                + MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck,
+        );
+    }
+
+    /// Configure various settings of stage 1 of the EL1 translation regime.
+    fn configure_translation_control(&self) {
+        let t1sz = (64 - bsp::memory::mmu::KernelAddrSpace::SIZE_SHIFT) as u64;
+
+        TCR_EL1.write(
+            TCR_EL1::TBI1::Used
+                + TCR_EL1::IPS::Bits_40
+                + TCR_EL1::TG1::KiB_64
+                + TCR_EL1::SH1::Inner
+                + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::EPD1::EnableTTBR1Walks
+                + TCR_EL1::A1::TTBR1
+                + TCR_EL1::T1SZ.val(t1sz)
+                + TCR_EL1::EPD0::DisableTTBR0Walks,
+        );
+    }
+
+    /// Drop all of this core's EL1 TLB entries.
+    #[inline(always)]
+    fn invalidate_tlb(&self) {
+        unsafe { asm!("tlbi vmalle1", options(nostack)) };
+        barrier::dsb(barrier::NSH);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Enable the EL1 MMU and caches with the boot tables. Called by every core from `_start_rust()` in
+/// EL2, before dropping to EL1 in the higher half.
+///
+/// Returns false if the HW lacks the 64 KiB granule, there is no way to report that this early.
+///
+/// # Safety
+///
+/// - Runs with the MMU off at the physical load address. Everything in here has to be position
+///   independent: no panics and no absolute addresses taken from statics.
+pub unsafe fn enable_mmu_and_caching_early() -> bool {
+    if !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported) {
+        return false;
+    }
+
+    MMU.set_up_mair();
+
+    // every core rebuilds them, the content is always the same
+    BOOT_TABLES.populate_boot_entries();
+    TTBR1_EL1.set_baddr(BOOT_TABLES.boot_phys_base_address());
+
+    MMU.configure_translation_control();
+
+    // Force all previous changes to be seen before the MMU is enabled.
+    barrier::isb(barrier::SY);
+
+    // Takes effect once the core is in EL1.
+    SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+
+    barrier::isb(barrier::SY);
+
+    true
+}
+
+/// Return a reference to the MMU instance.
+pub fn mmu() -> &'static impl memory::mmu::interface::MMU {
+    &MMU
+}
+
+/// Map `num_pages` pages at `virt_addr` to `phys_addr` in the live kernel tables, which all cores
+/// share. The pages must not be mapped yet.
+///
+/// # Safety
+///
+/// - Calls must be serialized by the caller.
+/// - Does not check whether the physical memory is in use elsewhere.
+pub unsafe fn kernel_map_pages(
+    virt_addr: usize,
+    phys_addr: usize,
+    num_pages: usize,
+    attribute_fields: &AttributeFields,
+) -> Result<(), &'static str> {
+    if !KERNEL_TABLES_READY.load(Ordering::Acquire) {
+        return Err("Kernel translation tables not ready");
+    }
+
+    KERNEL_TABLES.map_pages(virt_addr, phys_addr, num_pages, attribute_fields)?;
+
+    // Invalid entries never make it into a TLB, so there is nothing to invalidate. The new entries
+    // only have to reach the table walkers of all cores.
+    barrier::dsb(barrier::ISHST);
+    barrier::isb(barrier::SY);
+
+    Ok(())
+}
+
+/// Unmap `num_pages` pages at `virt_addr` from the live kernel tables, on all cores.
+///
+/// # Safety
+///
+/// - Calls must be serialized by the caller.
+/// - Nothing may access the pages anymore.
+pub unsafe fn kernel_unmap_pages(virt_addr: usize, num_pages: usize) -> Result<(), &'static str> {
+    if !KERNEL_TABLES_READY.load(Ordering::Acquire) {
+        return Err("Kernel translation tables not ready");
+    }
+
+    KERNEL_TABLES.unmap_pages(virt_addr, num_pages)?;
+
+    // The invalid entries have to reach the table walkers before the old ones are dropped from the
+    // TLBs of all cores, or a walk could bring them right back.
+    barrier::dsb(barrier::ISHST);
+    asm!("tlbi vmalle1is", options(nostack));
+    barrier::dsb(barrier::ISH);
+    barrier::isb(barrier::SY);
+
+    Ok(())
+}
+
+//* for the OS
+
+impl memory::mmu::interface::MMU for MemoryManagementUnit {
+    unsafe fn install_kernel_tables(&self) -> Result<(), MMUEnableError> {
+        if !self.is_enabled() {
+            return Err(MMUEnableError::Other("Boot code did not enable the MMU"));
+        }
+
+        // Populate translation tables. Only the boot core gets here before they are ready, the
+        // secondary cores are started later.
+        if !KERNEL_TABLES_READY.load(Ordering::Acquire) {
+            KERNEL_TABLES
+                .populate_tt_entries()
+                .map_err(MMUEnableError::Other)?;
+            KERNEL_TABLES_READY.store(true, Ordering::Release);
+        }
+
+        if TTBR1_EL1.get_baddr() == KERNEL_TABLES.phys_base_address() {
+            return Err(MMUEnableError::AlreadyEnabled);
+        }
+
+        // The new tables map the kernel and DRAM at the same physical addresses as the boot
+        // tables, so the switch can happen under the running code. Device memory is gone until
+        // drivers map it again, only the panic console may have touched it so far, and gets mapped
+        // right after. Table walks are cacheable, the stores only need to complete.
+        barrier::dsb(barrier::ISHST);
+        TTBR1_EL1.set_baddr(KERNEL_TABLES.phys_base_address());
+        barrier::isb(barrier::SY);
+
+        // The 512 MiB boot blocks must not linger next to the new 64 KiB pages.
+        self.invalidate_tlb();
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn is_enabled(&self) -> bool {
+        SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
+    }
+}
@@ -0,0 +1,457 @@
+//! Architectural translation table.
+//!
+//! Only 64 KiB granule is supported.
+
+use crate::{
+    bsp, memory,
+    memory::mmu::{
+        arch_mmu::{mair, Granule512MiB, Granule64KiB},
+        AccessPermissions, AttributeFields, MemAttributes,
+    },
+};
+use core::convert;
+use tock_registers::{
+    fields::FieldValue,
+    interfaces::{Readable, Writeable},
+    register_bitfields,
+    registers::InMemoryRegister,
+};
+
+// A table descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-15.
+register_bitfields! {u64,
+    STAGE1_TABLE_DESCRIPTOR [
+        /// Physical address of the next descriptor.
+        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+
+        TYPE  OFFSET(1) NUMBITS(1) [
+            Block = 0,
+            Table = 1
+        ],
+
+        VALID OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ]
+    ]
+}
+
+// A level 3 page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17.
+register_bitfields! {u64,
+    STAGE1_PAGE_DESCRIPTOR [
+        /// Unprivileged execute-never.
+        UXN      OFFSET(54) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Privileged execute-never.
+        PXN      OFFSET(53) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
+        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+
+        /// Access flag.
+        AF       OFFSET(10) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
+        /// Shareability field.
+        SH       OFFSET(8) NUMBITS(2) [
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ],
+
+        /// Access Permissions.
+        AP       OFFSET(6) NUMBITS(2) [
+            RW_EL1 = 0b00,
+            RW_EL1_EL0 = 0b01,
+            RO_EL1 = 0b10,
+            RO_EL1_EL0 = 0b11
+        ],
+
+        /// Memory attributes index into the MAIR_EL1 register.
+        AttrIndx OFFSET(2) NUMBITS(3) [],
+
+        TYPE     OFFSET(1) NUMBITS(1) [
+            Reserved_Invalid = 0,
+            Page = 1
+        ],
+
+        VALID    OFFSET(0) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ]
+    ]
+}
+
+/// A level 2 block descriptor with 512 MiB aperture.
+///
+/// Same layout as a page descriptor, except that TYPE is 0 and the output address is 512 MiB
+/// aligned.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct BlockDescriptor {
+    value: u64,
+}
+
+/// A table descriptor for 64 KiB aperture.
+///
+/// The output points to the next table.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct TableDescriptor {
+    value: u64,
+}
+
+/// A page descriptor with 64 KiB aperture.
+///
+/// The output points to physical memory.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct PageDescriptor {
+    value: u64,
+}
+
+trait StartAddr {
+    fn phys_start_addr_u64(&self) -> u64;
+    fn phys_start_addr_usize(&self) -> usize;
+}
+
+const NUM_LVL2_TABLES: usize = bsp::memory::mmu::KernelAddrSpace::SIZE >> Granule512MiB::SHIFT;
+
+/// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
+/// aligned, so the lvl3 is put first.
+#[repr(C)]
+#[repr(align(65536))]
+pub struct FixedSizeTranslationTable<const NUM_TABLES: usize> {
+    /// Page descriptors, covering 64 KiB windows per entry.
+    lvl3: [[PageDescriptor; 8192]; NUM_TABLES],
+
+    /// Table descriptors, covering 512 MiB windows.
+    lvl2: [TableDescriptor; NUM_TABLES],
+}
+
+/// A translation table type for the kernel space.
+pub type KernelTranslationTable = FixedSizeTranslationTable<NUM_LVL2_TABLES>;
+
+/// The tables the MMU gets enabled with at boot. A single level of 512 MiB blocks, mapping all of
+/// physical memory linearly into the kernel's half.
+#[repr(C)]
+#[repr(align(64))]
+pub struct BootTranslationTable {
+    lvl2: [BlockDescriptor; NUM_LVL2_TABLES],
+}
+
+// The tables are only ever accessed through the kernel's linear mapping.
+impl<T, const N: usize> StartAddr for [T; N] {
+    fn phys_start_addr_u64(&self) -> u64 {
+        self.phys_start_addr_usize() as u64
+    }
+
+    fn phys_start_addr_usize(&self) -> usize {
+        memory::virt_to_phys(self as *const _ as usize)
+    }
+}
+
+impl BlockDescriptor {
+    /// Create an instance.
+    ///
+    /// Descriptor is invalid, aka the VALID bit is cleared.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance.
+    pub fn from_output_addr(phys_output_addr: usize, attribute_fields: &AttributeFields) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_output_addr as u64 >> Granule64KiB::SHIFT;
+        val.write(
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted)
+                + STAGE1_PAGE_DESCRIPTOR::AF::True
+                + STAGE1_PAGE_DESCRIPTOR::TYPE::Reserved_Invalid // a block at level 2
+                + STAGE1_PAGE_DESCRIPTOR::VALID::True
+                + (*attribute_fields).into(),
+        );
+
+        Self { value: val.get() }
+    }
+}
+
+impl TableDescriptor {
+    /// Create an instance.
+    ///
+    /// Descriptor is invalid, aka the VALID bit is cleared.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance pointing to the supplied address.
+    pub fn from_next_lvl_table_addr(phys_next_lvl_table_addr: usize) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_next_lvl_table_addr >> Granule64KiB::SHIFT;
+        val.write(
+            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
+                + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
+                + STAGE1_TABLE_DESCRIPTOR::VALID::True,
+        );
+
+        TableDescriptor { value: val.get() }
+    }
+}
+
+/// Convert the kernel's generic memory attributes to HW-specific attributes of the MMU.
+impl convert::From<AttributeFields> for FieldValue<u64, STAGE1_PAGE_DESCRIPTOR::Register> {
+    fn from(attribute_fields: AttributeFields) -> Self {
+        // Memory attributes.
+        let mut desc = match attribute_fields.mem_attributes {
+            MemAttributes::CacheableDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
+            }
+            MemAttributes::NonCacheableDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL_NON_CACHEABLE)
+            }
+            MemAttributes::Device => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
+            }
+        };
+
+        // Access Permissions.
+        desc += match attribute_fields.acc_perms {
+            AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
+            AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+        };
+
+        // The execute-never attribute is mapped to PXN in AArch64.
+        desc += if attribute_fields.execute_never {
+            STAGE1_PAGE_DESCRIPTOR::PXN::True
+        } else {
+            STAGE1_PAGE_DESCRIPTOR::PXN::False
+        };
+
+        // Always set unprivileged exectue-never as long as userspace is not implemented yet.
+        desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
+
+        desc
+    }
+}
+
+impl PageDescriptor {
+    /// Create an instance.
+    ///
+    /// Descriptor is invalid, aka the VALID bit is cleared.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance.
+    pub fn from_output_addr(phys_output_addr: usize, attribute_fields: &AttributeFields) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = phys_output_addr as u64 >> Granule64KiB::SHIFT;
+        val.write(
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted)
+                + STAGE1_PAGE_DESCRIPTOR::AF::True
+                + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
+                + STAGE1_PAGE_DESCRIPTOR::VALID::True
+                + (*attribute_fields).into(),
+        );
+
+        Self { value: val.get() }
+    }
+
+    /// Returns the valid bit.
+    fn is_valid(&self) -> bool {
+        InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_PAGE_DESCRIPTOR::VALID)
+    }
+}
+
+impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        // Can't have a zero-sized address space.
+        assert!(NUM_TABLES > 0);
+
+        Self {
+            lvl3: [[PageDescriptor::new_zeroed(); 8192]; NUM_TABLES],
+            lvl2: [TableDescriptor::new_zeroed(); NUM_TABLES],
+        }
+    }
+
+    /// Iterates over all static translation table entries and fills them at once.
+    ///
+    /// # Safety
+    ///
+    /// - Modifies a `static mut`. Ensure it only happens from here.
+    pub unsafe fn populate_tt_entries(&mut self) -> Result<(), &'static str> {
+        for (l2_nr, l2_entry) in self.lvl2.iter_mut().enumerate() {
+            *l2_entry =
+                TableDescriptor::from_next_lvl_table_addr(self.lvl3[l2_nr].phys_start_addr_usize());
+
+            for (l3_nr, l3_entry) in self.lvl3[l2_nr].iter_mut().enumerate() {
+                let virt_addr = bsp::memory::mmu::KERNEL_VIRT_START
+                    + (l2_nr << Granule512MiB::SHIFT)
+                    + (l3_nr << Granule64KiB::SHIFT);
+
+                let properties =
+                    bsp::memory::mmu::virt_mem_layout().virt_addr_properties(virt_addr)?;
+
+                // unmapped pages stay invalid
+                *l3_entry = match properties {
+                    Some((phys_output_addr, attribute_fields)) => {
+                        PageDescriptor::from_output_addr(phys_output_addr, &attribute_fields)
+                    }
+                    None => PageDescriptor::new_zeroed(),
+                };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The lvl2 and lvl3 indices of the page containing `virt_addr`.
+    fn lvl2_lvl3_index_from(&self, virt_addr: usize) -> Result<(usize, usize), &'static str> {
+        let offset = virt_addr
+            .checked_sub(bsp::memory::mmu::KERNEL_VIRT_START)
+            .ok_or("Virtual address not in the kernel's address space")?;
+
+        let lvl2_index = offset >> Granule512MiB::SHIFT;
+        let lvl3_index = (offset & Granule512MiB::MASK) >> Granule64KiB::SHIFT;
+        if lvl2_index >= NUM_TABLES {
+            return Err("Virtual address not covered by the tables");
+        }
+
+        Ok((lvl2_index, lvl3_index))
+    }
+
+    /// Map `num_pages` pages starting at `virt_addr` to physical memory starting at
+    /// `phys_output_addr`. None of the pages may be mapped yet. Both addresses must be page
+    /// aligned.
+    ///
+    /// # Safety
+    ///
+    /// - Modifies live translation tables. The caller must serialize calls, and make the new
+    ///   entries visible to the table walkers afterwards.
+    pub unsafe fn map_pages(
+        &mut self,
+        virt_addr: usize,
+        phys_output_addr: usize,
+        num_pages: usize,
+        attribute_fields: &AttributeFields,
+    ) -> Result<(), &'static str> {
+        if virt_addr & Granule64KiB::MASK != 0 || phys_output_addr & Granule64KiB::MASK != 0 {
+            return Err("Addresses not page aligned");
+        }
+
+        // check everything first, so that nothing is left half-mapped
+        for page in 0..num_pages {
+            let (lvl2_index, lvl3_index) =
+                self.lvl2_lvl3_index_from(virt_addr + (page << Granule64KiB::SHIFT))?;
+
+            if self.lvl3[lvl2_index][lvl3_index].is_valid() {
+                return Err("Virtual page already mapped");
+            }
+        }
+
+        for page in 0..num_pages {
+            let offset = page << Granule64KiB::SHIFT;
+            let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from(virt_addr + offset)?;
+
+            self.lvl3[lvl2_index][lvl3_index] =
+                PageDescriptor::from_output_addr(phys_output_addr + offset, attribute_fields);
+        }
+
+        Ok(())
+    }
+
+    /// Unmap `num_pages` pages starting at `virt_addr`. Pages that are not mapped stay that way.
+    ///
+    /// # Safety
+    ///
+    /// - Modifies live translation tables. The caller must serialize calls, and drop the old
+    ///   entries from the TLBs afterwards.
+    pub unsafe fn unmap_pages(
+        &mut self,
+        virt_addr: usize,
+        num_pages: usize,
+    ) -> Result<(), &'static str> {
+        if virt_addr & Granule64KiB::MASK != 0 {
+            return Err("Address not page aligned");
+        }
+
+        // check everything first, so that nothing is left half-unmapped
+        for page in 0..num_pages {
+            self.lvl2_lvl3_index_from(virt_addr + (page << Granule64KiB::SHIFT))?;
+        }
+
+        for page in 0..num_pages {
+            let (lvl2_index, lvl3_index) =
+                self.lvl2_lvl3_index_from(virt_addr + (page << Granule64KiB::SHIFT))?;
+
+            self.lvl3[lvl2_index][lvl3_index] = PageDescriptor::new_zeroed();
+        }
+
+        Ok(())
+    }
+
+    /// The translation table's base address to be used for programming the MMU.
+    pub fn phys_base_address(&self) -> u64 {
+        self.lvl2.phys_start_addr_u64()
+    }
+}
+
+impl BootTranslationTable {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            lvl2: [BlockDescriptor::new_zeroed(); NUM_LVL2_TABLES],
+        }
+    }
+
+    /// Fill the table. Blocks overlapping the `BSP`'s device range are device memory, everything
+    /// else is normal cacheable memory and executable.
+    ///
+    /// # Safety
+    ///
+    /// - Runs with the MMU off, before the kernel is in the higher half. Must stay free of panics
+    ///   and absolute addresses.
+    pub unsafe fn populate_boot_entries(&mut self) {
+        let device = bsp::memory::mmu::BOOT_DEVICE_RANGE_INCLUSIVE;
+
+        for (l2_nr, l2_entry) in self.lvl2.iter_mut().enumerate() {
+            let phys_addr = l2_nr << Granule512MiB::SHIFT;
+            let phys_end_inclusive = phys_addr + (Granule512MiB::SIZE - 1);
+
+            let mem_attributes =
+                if phys_addr <= *device.end() && phys_end_inclusive >= *device.start() {
+                    MemAttributes::Device
+                } else {
+                    MemAttributes::CacheableDRAM
+                };
+            let attribute_fields = AttributeFields {
+                mem_attributes,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: matches!(mem_attributes, MemAttributes::Device),
+            };
+
+            *l2_entry = BlockDescriptor::from_output_addr(phys_addr, &attribute_fields);
+        }
+    }
+
+    /// The table's base address to be used for programming the MMU.
+    ///
+    /// Only valid while running at the physical load address, where the address of a static is its
+    /// physical address.
+    pub fn boot_phys_base_address(&self) -> u64 {
+        self.lvl2.as_ptr() as u64
+    }
+}
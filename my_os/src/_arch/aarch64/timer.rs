@@ -1,18 +1,18 @@
 //! Architectural timer primitives.
 
-use crate::warn;
 use aarch64_cpu::{asm::barrier, registers::*};
 use core::{
     num::{NonZeroU128, NonZeroU32, NonZeroU64},
     ops::{Add, Div},
     time::Duration,
 };
-use tock_registers::interfaces::Readable;
+use tock_registers::interfaces::{Readable, Writeable};
 
 const NANOSEC_PER_SEC: NonZeroU64 = NonZeroU64::new(1_000_000_000).unwrap();
 
-#[derive(Copy, Clone, PartialOrd, PartialEq)]
-struct GenericTimerCounterValue(u64);
+/// A value of the physical counter, in ticks of `CNTFRQ_EL0`.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
+pub struct GenericTimerCounterValue(u64);
 
 /// Boot assembly code overwrites this value with the value of CNTFRQ_EL0
 #[no_mangle] // so compiler doesnt change anything
@@ -25,6 +25,14 @@ fn arch_timer_counter_freq() -> NonZeroU32 {
 
 impl GenericTimerCounterValue {
     pub const MAX: Self = GenericTimerCounterValue(u64::MAX);
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(GenericTimerCounterValue)
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        GenericTimerCounterValue(self.0.saturating_sub(other.0))
+    }
 }
 
 // addition operator overloading
@@ -83,14 +91,20 @@ impl TryFrom<Duration> for GenericTimerCounterValue {
     }
 }
 
+/// The current value of the physical counter.
 #[inline(always)]
-fn read_cntpct() -> GenericTimerCounterValue {
+pub fn read_cntpct() -> GenericTimerCounterValue {
     barrier::isb(barrier::SY); // block OOO execution
     let cnt = CNTPCT_EL0.get();
 
     GenericTimerCounterValue(cnt)
 }
 
+/// The counter's frequency, as `CNTFRQ_EL0` has it.
+pub fn frequency() -> NonZeroU64 {
+    arch_timer_counter_freq().into()
+}
+
 /// The timer's resolution.
 pub fn resolution() -> Duration {
     Duration::from(GenericTimerCounterValue(1))
@@ -101,19 +115,23 @@ pub fn uptime() -> Duration {
     read_cntpct().into()
 }
 
-/// Spin for a given duration.
-pub fn spin_for(duration: Duration) {
-    let curr_counter_value = read_cntpct();
+/// Arm the executing core's timer to raise its IRQ at `due_time`, an uptime.
+pub fn set_timeout_irq(due_time: Duration) {
+    let delay = due_time.saturating_sub(uptime());
 
-    let counter_value_delta: GenericTimerCounterValue = match duration.try_into() {
-        Err(msg) => {
-            warn!("spin_for: {}. Skipping", msg);
-            return;
-        }
-        Ok(val) => val,
+    // rounded up, so that the IRQ doesn't come a tick before the timeout is due
+    let ticks = match GenericTimerCounterValue::try_from(delay) {
+        Err(_) => GenericTimerCounterValue::MAX,
+        Ok(val) => val + GenericTimerCounterValue(1),
     };
-    let counter_value_target = curr_counter_value + counter_value_delta;
 
-    // spin
-    while GenericTimerCounterValue(CNTPCT_EL0.get()) < counter_value_target {}
+    // TVAL is a signed 32 bit down counter. Timeouts further out than that take a few IRQs, each
+    // of which re-arms the timer for what is left.
+    CNTP_TVAL_EL0.set(ticks.0.min(i32::MAX as u64));
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+/// Switch the executing core's timer off, which also drops its IRQ.
+pub fn conclude_timeout_irq() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR);
 }
@@ -1,6 +1,10 @@
 //! Device driver.
 
+#[cfg(feature = "bsp_rpi4")]
+mod arm;
 mod bcm;
 mod common;
 
+#[cfg(feature = "bsp_rpi4")]
+pub use arm::*;
 pub use bcm::*;
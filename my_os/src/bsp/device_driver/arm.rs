@@ -0,0 +1,5 @@
+//! ARM driver top level.
+
+mod gicv2;
+
+pub use gicv2::*;
@@ -0,0 +1,153 @@
+//! GICv2 Driver - ARM Generic Interrupt Controller v2.
+//!
+//! The GIC-400 on the BCM2711 is split into two parts:
+//! - The distributor (GICD), shared by all cores, which enables IRQs and routes them to cores.
+//! - The CPU interface (GICC), banked per core, which hands out pending IRQs and takes the end of
+//!   interrupt notification.
+//!
+//! # Glossary
+//!   - SGI - Software Generated Interrupt, IDs 0..=15.
+//!   - PPI - Private Peripheral Interrupt, IDs 16..=31 (e.g. the per core timers).
+//!   - SPI - Shared Peripheral Interrupt, IDs 32.. (e.g. the UART).
+
+mod gicc;
+mod gicd;
+
+use crate::{
+    bsp,
+    common::BoundedUsize,
+    cpu, drivers,
+    exception::{self, asynchronous::IRQHandlerDescriptor},
+    synchronization::{self, InitStateLock},
+};
+
+type HandlerTable = [Option<IRQHandlerDescriptor<IRQNumber>>; IRQNumber::MAX_INCLUSIVE + 1];
+
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+pub type IRQNumber = BoundedUsize<{ GICv2::MAX_IRQ_NUMBER }>;
+
+/// Representation of the GIC.
+pub struct GICv2 {
+    /// The Distributor.
+    gicd: gicd::GICD,
+
+    /// The CPU Interface.
+    gicc: gicc::GICC,
+
+    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
+    handler_table: InitStateLock<HandlerTable>,
+}
+
+impl GICv2 {
+    // Normally 1019, but keep it lower to save some space. The BCM2711 tops out below 300.
+    const MAX_IRQ_NUMBER: usize = 300;
+
+    pub const COMPATIBLE: &'static str = "GICv2 (ARM Generic Interrupt Controller v2)";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct physical MMIO start addresses.
+    pub const unsafe fn new(
+        phys_gicd_mmio_start_addr: usize,
+        phys_gicc_mmio_start_addr: usize,
+    ) -> Self {
+        Self {
+            gicd: gicd::GICD::new(phys_gicd_mmio_start_addr),
+            gicc: gicc::GICC::new(phys_gicc_mmio_start_addr),
+            handler_table: InitStateLock::new([None; IRQNumber::MAX_INCLUSIVE + 1]),
+        }
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::ReadWriteEx;
+
+impl drivers::interface::DeviceDriver for GICv2 {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.gicd.map_registers()?;
+        self.gicc.map_registers()?;
+
+        if bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id() {
+            self.gicd.boot_core_init();
+        }
+
+        self.gicc.priority_accept_all();
+        self.gicc.enable();
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQManager for GICv2 {
+    type IRQNumberType = IRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_handler_descriptor: IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        self.handler_table.write(|table| {
+            let irq_number = irq_handler_descriptor.number().get();
+
+            if table[irq_number].is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            table[irq_number] = Some(irq_handler_descriptor);
+
+            Ok(())
+        })
+    }
+
+    fn enable(&self, irq_number: &Self::IRQNumberType) {
+        self.gicd.enable(irq_number);
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        // Extract the highest priority pending IRQ number from the Interrupt Acknowledge Register
+        // (IAR).
+        let irq_number = self.gicc.pending_irq_number(ic);
+
+        // Guard against spurious interrupts.
+        if irq_number > GICv2::MAX_IRQ_NUMBER {
+            return;
+        }
+
+        // Call the IRQ handler. Panic if there is none.
+        self.handler_table.read(|table| {
+            match table[irq_number] {
+                None => panic!("No handler registered for IRQ {}", irq_number),
+                Some(descriptor) => {
+                    // Call the IRQ handler. Panics on failure.
+                    descriptor.handler().handle().expect("Error handling IRQ");
+                }
+            }
+        });
+
+        // Signal completion of handling.
+        self.gicc.mark_completed(irq_number as u32, ic);
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Peripheral handler:");
+
+        self.handler_table.read(|table| {
+            for (i, opt) in table.iter().enumerate() {
+                if let Some(handler) = opt {
+                    info!("            {: >3}. {}", i, handler.name());
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,146 @@
+//! GICC Driver - GIC CPU interface.
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    exception::asynchronous::IRQContext,
+    synchronization::{self, InitStateLock},
+};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::ReadWrite,
+};
+
+register_bitfields! {
+    u32,
+
+    /// CPU Interface Control Register
+    CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Priority Mask Register
+    PMR [
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    /// Interrupt Acknowledge Register
+    IAR [
+        InterruptID OFFSET(0) NUMBITS(10) []
+    ],
+
+    /// End of Interrupt Register
+    EOIR [
+        EOIINTID OFFSET(0) NUMBITS(10) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub RegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
+        (0x004 => PMR: ReadWrite<u32, PMR::Register>),
+        (0x008 => _reserved1),
+        (0x00C => IAR: ReadWrite<u32, IAR::Register>),
+        (0x010 => EOIR: ReadWrite<u32, EOIR::Register>),
+        (0x014 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// Representation of the GIC CPU interface.
+///
+/// The CPU interface registers are banked, so every core sees its own copy and no lock is needed.
+/// The `InitStateLock` only covers mapping them at init.
+pub struct GICC {
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
+    registers: InitStateLock<Registers>,
+}
+
+//* for the OS
+
+use synchronization::interface::ReadWriteEx;
+
+impl GICC {
+    const NAME: &'static str = "GICv2 CPU Interface";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
+        Self {
+            phys_mmio_start_addr,
+            registers: InitStateLock::new(Registers::new(phys_mmio_start_addr)),
+        }
+    }
+
+    /// Map the registers.
+    pub fn map_registers(&self) -> Result<(), &'static str> {
+        let registers = Registers::map(Self::NAME, self.phys_mmio_start_addr)?;
+
+        self.registers.write(|regs| *regs = registers);
+
+        Ok(())
+    }
+
+    /// Accept interrupts of any priority.
+    ///
+    /// Quote from the GICv2 Architecture Specification:
+    ///
+    ///   "Writing 255 to the GICC_PMR always sets it to the largest supported priority field
+    ///    value."
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn priority_accept_all(&self) {
+        self.registers
+            .read(|regs| regs.PMR.write(PMR::Priority.val(255))); // Comment in arch spec.
+    }
+
+    /// Enable the interface - start accepting IRQs.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn enable(&self) {
+        self.registers
+            .read(|regs| regs.CTLR.write(CTLR::Enable::SET));
+    }
+
+    /// Extract the number of the highest-priority pending IRQ.
+    ///
+    /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn pending_irq_number<'irq_context>(&self, _ic: &IRQContext<'irq_context>) -> usize {
+        self.registers
+            .read(|regs| regs.IAR.read(IAR::InterruptID) as usize)
+    }
+
+    /// Complete handling of the currently active IRQ.
+    ///
+    /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
+    ///
+    /// To be called after `pending_irq_number()`.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn mark_completed<'irq_context>(&self, irq_number: u32, _ic: &IRQContext<'irq_context>) {
+        self.registers
+            .read(|regs| regs.EOIR.write(EOIR::EOIINTID.val(irq_number)));
+    }
+}
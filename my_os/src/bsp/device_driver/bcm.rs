@@ -1,7 +1,17 @@
 //! BCM driver top level.
 
+mod bcm_2xxx_framebuffer;
 mod bcm_2xxx_gpio;
+#[cfg(feature = "bsp_rpi3")]
+mod bcm_2xxx_interrupt_controller;
+mod bcm_2xxx_mailbox;
 mod bcm_2xxx_pl011_uart;
+mod bcm_2xxx_system_timer;
 
+pub use bcm_2xxx_framebuffer::*;
 pub use bcm_2xxx_gpio::*;
+#[cfg(feature = "bsp_rpi3")]
+pub use bcm_2xxx_interrupt_controller::*;
+pub use bcm_2xxx_mailbox::*;
 pub use bcm_2xxx_pl011_uart::*;
+pub use bcm_2xxx_system_timer::*;
@@ -0,0 +1,259 @@
+//! VideoCore framebuffer driver.
+//!
+//! The firmware owns the display. Asked over the mailbox, it sets up a screen and hands out a
+//! framebuffer in the memory it keeps for itself. It may settle on a different size than the one
+//! asked for, which is taken as is. The depth has to come out as 32 bit though, the drawing code
+//! knows no other.
+//!
+//! The framebuffer is mapped normal non-cacheable rather than as device memory. Writes get gathered
+//! on their way to the VideoCore, and scrolling can use plain memory copies.
+//!
+//! Descriptions taken from
+//! - https://github.com/raspberrypi/firmware/wiki/Mailbox-framebuffer-interface
+
+use super::bcm_2xxx_mailbox::{property, Mailbox, PixelOrder};
+use crate::{
+    drivers, framebuffer::Color, memory, synchronization, synchronization::InitStateLock, warn,
+};
+use core::{fmt, ops::RangeInclusive, ptr, slice};
+
+/// Bits per pixel.
+const DEPTH: u32 = 32;
+
+const BYTES_PER_PIXEL: usize = DEPTH as usize / 8;
+
+/// The firmware hands out VideoCore bus addresses. Their top two bits select the cache alias the
+/// VideoCore goes through, the ARM sees the memory at the address without them.
+const BUS_ADDR_MASK: usize = 0x3FFF_FFFF;
+
+/// What the firmware came up with.
+#[derive(Copy, Clone)]
+pub struct FramebufferInfo {
+    /// Where the framebuffer is mapped.
+    virt_start_addr: usize,
+    phys_start_addr: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Bytes per line.
+    pub pitch: usize,
+    pub pixel_order: PixelOrder,
+}
+
+/// Representation of the framebuffer.
+pub struct Framebuffer {
+    mailbox: &'static Mailbox,
+    requested_width: u32,
+    requested_height: u32,
+
+    /// Set during kernel init, `None` if the firmware did not give out a framebuffer.
+    info: InitStateLock<Option<FramebufferInfo>>,
+}
+
+impl FramebufferInfo {
+    fn pixel_value(&self, color: Color) -> u32 {
+        let (low, high) = match self.pixel_order {
+            PixelOrder::Rgb => (color.r, color.b),
+            PixelOrder::Bgr => (color.b, color.r),
+        };
+
+        u32::from(low) | (u32::from(color.g) << 8) | (u32::from(high) << 16)
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        (self.virt_start_addr + y * self.pitch + x * BYTES_PER_PIXEL) as *mut u32
+    }
+
+    /// Pixels `x..x_end` of line `y`, which must be on the screen.
+    ///
+    /// # Safety
+    ///
+    /// - The slice aliases the framebuffer, not `self`. Callers must not hold on to it or have two
+    ///   of them overlap.
+    unsafe fn line<'a>(&self, x: usize, x_end: usize, y: usize) -> &'a mut [u32] {
+        slice::from_raw_parts_mut(self.pixel_ptr(x, y), x_end - x)
+    }
+}
+
+impl Framebuffer {
+    pub const COMPATIBLE: &'static str = "BCM VideoCore Framebuffer";
+
+    /// Create an instance that asks the firmware for a `width` x `height` screen.
+    pub const fn new(mailbox: &'static Mailbox, width: u32, height: u32) -> Self {
+        Self {
+            mailbox,
+            requested_width: width,
+            requested_height: height,
+            info: InitStateLock::new(None),
+        }
+    }
+
+    /// Set up the screen and allocate the framebuffer, in one message, since the firmware only
+    /// takes the settings into account for an allocation in the same message.
+    fn allocate(&self) -> Result<FramebufferInfo, &'static str> {
+        let (width, height) = (self.requested_width, self.requested_height);
+
+        let (physical_size, depth, pixel_order, buffer, pitch) = self.mailbox.message(
+            |msg| {
+                let physical_size = msg.add(&property::SetPhysicalSize { width, height })?;
+                msg.add(&property::SetVirtualSize { width, height })?;
+                msg.add(&property::SetVirtualOffset { x: 0, y: 0 })?;
+                let depth = msg.add(&property::SetDepth(DEPTH))?;
+                let pixel_order = msg.add(&property::SetPixelOrder(PixelOrder::Rgb))?;
+                let buffer = msg.add(&property::AllocateBuffer { alignment: 4096 })?;
+                let pitch = msg.add(&property::GetPitch)?;
+
+                Ok((physical_size, depth, pixel_order, buffer, pitch))
+            },
+            |(physical_size, depth, pixel_order, buffer, pitch), answer| {
+                Ok((
+                    physical_size.response(answer)?,
+                    depth.response(answer)?,
+                    pixel_order.response(answer)?,
+                    buffer.response(answer)?,
+                    pitch.response(answer)?,
+                ))
+            },
+        )?;
+
+        if depth != DEPTH {
+            return Err("Firmware did not agree to 32 bit pixels");
+        }
+
+        let (width, height) = (physical_size.0 as usize, physical_size.1 as usize);
+        let pitch = pitch as usize;
+        if buffer.base == 0 || width == 0 || height == 0 {
+            return Err("Firmware did not allocate a framebuffer");
+        }
+
+        if pitch < width * BYTES_PER_PIXEL || buffer.size < pitch * height {
+            return Err("Framebuffer smaller than the screen");
+        }
+
+        let phys_start_addr = buffer.base & BUS_ADDR_MASK;
+        let virt_start_addr = memory::mmu::kernel_map_mmio_write_combining(
+            Self::COMPATIBLE,
+            &RangeInclusive::new(phys_start_addr, phys_start_addr + buffer.size - 1),
+        )?;
+
+        // the linear map had it cacheable until the BSP took the VideoCore's memory out of it,
+        // whatever got speculatively cached back then must not shadow the new mapping
+        memory::invalidate_dcache_range(virt_start_addr, buffer.size);
+
+        Ok(FramebufferInfo {
+            virt_start_addr,
+            phys_start_addr,
+            width,
+            height,
+            pitch,
+            pixel_order,
+        })
+    }
+
+    /// What the firmware came up with, `None` if there is no framebuffer.
+    pub fn info(&self) -> Option<FramebufferInfo> {
+        self.info.read(|info| *info)
+    }
+}
+
+impl fmt::Display for FramebufferInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pixel_order = match self.pixel_order {
+            PixelOrder::Rgb => "RGB",
+            PixelOrder::Bgr => "BGR",
+        };
+
+        write!(
+            f,
+            "{}x{}, {} bit {}, pitch {} bytes, at {:#010x}",
+            self.width, self.height, DEPTH, pixel_order, self.pitch, self.phys_start_addr
+        )
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::ReadWriteEx;
+
+impl drivers::interface::DeviceDriver for Framebuffer {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    // no display is no reason not to boot
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        match self.allocate() {
+            Ok(info) => self.info.write(|i| *i = Some(info)),
+            Err(x) => warn!("{}: {}", Self::COMPATIBLE, x),
+        }
+
+        Ok(())
+    }
+}
+
+impl crate::framebuffer::interface::Framebuffer for Framebuffer {
+    fn width(&self) -> usize {
+        self.info().map_or(0, |info| info.width)
+    }
+
+    fn height(&self) -> usize {
+        self.info().map_or(0, |info| info.height)
+    }
+
+    fn put_pixel(&self, x: usize, y: usize, color: Color) {
+        self.put_pixels(x, y, &[color]);
+    }
+
+    fn put_pixels(&self, x: usize, y: usize, pixels: &[Color]) {
+        self.info.read(|info| match info {
+            Some(info) if x < info.width && y < info.height => {
+                let x_end = x.saturating_add(pixels.len()).min(info.width);
+                let line = unsafe { info.line(x, x_end, y) };
+
+                for (pixel, color) in line.iter_mut().zip(pixels) {
+                    *pixel = info.pixel_value(*color);
+                }
+            }
+            _ => (),
+        })
+    }
+
+    fn fill_rect(&self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        self.info.read(|info| {
+            let info = match info {
+                Some(info) if x < info.width => info,
+                _ => return,
+            };
+
+            let value = info.pixel_value(color);
+            let x_end = x.saturating_add(width).min(info.width);
+            let y_end = y.saturating_add(height).min(info.height);
+
+            for y in y..y_end {
+                unsafe { info.line(x, x_end, y) }.fill(value);
+            }
+        })
+    }
+
+    fn scroll_up(&self, rows: usize, color: Color) {
+        self.info.read(|info| {
+            let info = match info {
+                Some(info) => info,
+                None => return,
+            };
+
+            let rows = rows.min(info.height);
+
+            // the lines are `pitch` apart, so it all moves in one go
+            unsafe {
+                ptr::copy(
+                    info.pixel_ptr(0, rows) as *const u8,
+                    info.pixel_ptr(0, 0) as *mut u8,
+                    (info.height - rows) * info.pitch,
+                );
+            }
+        });
+
+        let height = self.height();
+        self.fill_rect(0, height - rows.min(height), self.width(), rows, color);
+    }
+}
@@ -21,6 +21,20 @@ register_bitfields! {
 
     /// GPIO Function Select 1
     GPFSEL1 [
+        /// Pin 17
+        FSEL17 OFFSET(21) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc3 = 0b111  // PL011 UART RTS
+        ],
+
+        /// Pin 16
+        FSEL16 OFFSET(18) NUMBITS(3) [
+            Input = 0b000,
+            Output = 0b001,
+            AltFunc3 = 0b111  // PL011 UART CTS
+        ],
+
         /// Pin 15
         FSEL15 OFFSET(15) NUMBITS(3) [
             Input = 0b000,
@@ -61,6 +75,23 @@ register_bitfields! {
             AssertClock = 1
         ]
     ],
+
+    /// GPIO Pull-up / Pull-down Register 0
+    ///
+    /// BCM2711 only.
+    GPIO_PUP_PDN_CNTRL_REG0 [
+        /// Pin 15
+        GPIO_PUP_PDN_CNTRL15 OFFSET(30) NUMBITS(2) [
+            NoResistor = 0b00,
+            PullUp = 0b01
+        ],
+
+        /// Pin 14
+        GPIO_PUP_PDN_CNTRL14 OFFSET(28) NUMBITS(2) [
+            NoResistor = 0b00,
+            PullUp = 0b01
+        ]
+    ]
 }
 
 // define register offset
@@ -73,7 +104,7 @@ register_structs! {
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
         (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
         (0x9C => _reserved3),
-        // (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
+        (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
         (0xE8 => @END),
     }
 }
@@ -87,6 +118,8 @@ struct GPIOInner {
 
 // wrapper that only lets 1 thing access GPIO at a time
 pub struct GPIO {
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
     inner: SpinLock<GPIOInner>,
 }
 
@@ -99,6 +132,7 @@ impl GPIOInner {
     }
 
     // for pi3
+    #[cfg(feature = "bsp_rpi3")]
     fn disable_pud_14_15_bcm2837(&mut self) {
         use crate::timer;
         use core::time::Duration;
@@ -117,6 +151,15 @@ impl GPIOInner {
         self.registers.GPPUDCLK0.set(0);
     }
 
+    // for pi4, no more clocking the setting in, each pin has its own 2 bits
+    #[cfg(feature = "bsp_rpi4")]
+    fn pull_up_14_15_bcm2711(&mut self) {
+        self.registers.GPIO_PUP_PDN_CNTRL_REG0.write(
+            GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL15::PullUp
+                + GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL14::PullUp,
+        );
+    }
+
     /// Map PL011 UART as standard output.
     /// TX to pin 14
     /// RX to pin 15
@@ -127,22 +170,54 @@ impl GPIOInner {
             .modify(GPFSEL1::FSEL15::AltFunc0 + GPFSEL1::FSEL14::AltFunc0);
 
         // Disable pull-up/down on pins 14 and 15.
+        #[cfg(feature = "bsp_rpi3")]
         self.disable_pud_14_15_bcm2837();
+
+        // The lines idle high, so pull them up on the Pi 4.
+        #[cfg(feature = "bsp_rpi4")]
+        self.pull_up_14_15_bcm2711();
+    }
+
+    /// Map the PL011 UART flow control lines.
+    /// CTS to pin 16
+    /// RTS to pin 17
+    pub fn map_pl011_uart_flow_control(&mut self) {
+        self.registers
+            .GPFSEL1
+            .modify(GPFSEL1::FSEL16::AltFunc3 + GPFSEL1::FSEL17::AltFunc3);
+    }
+
+    /// Return pins 16 and 17 to inputs.
+    pub fn unmap_pl011_uart_flow_control(&mut self) {
+        self.registers
+            .GPFSEL1
+            .modify(GPFSEL1::FSEL16::Input + GPFSEL1::FSEL17::Input);
     }
 }
 
 impl GPIO {
     pub const COMPATIBLE: &'static str = "BCM GPIO";
 
-    pub const unsafe fn new(mmio_addr: usize) -> Self {
+    // takes the physical address of the registers
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
         Self {
-            inner: SpinLock::new(GPIOInner::new(mmio_addr)),
+            phys_mmio_start_addr,
+            inner: SpinLock::new(GPIOInner::new(phys_mmio_start_addr)),
         }
     }
 
     pub fn map_pl011_uart(&self) {
         self.inner.lock(|inner| inner.map_pl011_uart())
     }
+
+    pub fn map_pl011_uart_flow_control(&self) {
+        self.inner.lock(|inner| inner.map_pl011_uart_flow_control())
+    }
+
+    pub fn unmap_pl011_uart_flow_control(&self) {
+        self.inner
+            .lock(|inner| inner.unmap_pl011_uart_flow_control())
+    }
 }
 
 use synchronization::interface::Mutex;
@@ -151,4 +226,12 @@ impl drivers::interface::DeviceDriver for GPIO {
     fn compatible(&self) -> &'static str {
         Self::COMPATIBLE
     }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let registers = Registers::map(Self::COMPATIBLE, self.phys_mmio_start_addr)?;
+
+        self.inner.lock(|inner| inner.registers = registers);
+
+        Ok(())
+    }
 }
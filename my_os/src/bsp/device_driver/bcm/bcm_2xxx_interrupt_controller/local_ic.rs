@@ -0,0 +1,180 @@
+//! Local Interrupt Controller Driver.
+//!
+//! Based on the "BCM2836 ARM-local peripherals" document (QA7_rev3.4), which the BCM2837 reuses.
+
+use super::{LocalIRQ, PendingIRQs};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu,
+    exception::{self, asynchronous::IRQHandlerDescriptor},
+    synchronization::{self, IRQSafeSpinLock, InitStateLock},
+};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_bitfields! {
+    u32,
+
+    /// GPU Interrupts Routing
+    GPU_INT_ROUTING [
+        /// Core that receives the GPU FIQ.
+        FIQ OFFSET(2) NUMBITS(2) [],
+
+        /// Core that receives the GPU IRQ.
+        IRQ OFFSET(0) NUMBITS(2) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x0C => GPU_INT_ROUTING: ReadWrite<u32, GPU_INT_ROUTING::Register>),
+        (0x10 => _reserved2),
+        (0x40 => CORE_TIMER_INT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x50 => CORE_MAILBOX_INT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+type HandlerTable = [Option<IRQHandlerDescriptor<LocalIRQ>>; LocalIRQ::MAX_INCLUSIVE + 1];
+
+/// Representation of the local (per core) interrupt controller.
+pub struct LocalIC {
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
+    registers: IRQSafeSpinLock<Registers>,
+
+    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
+    handler_table: InitStateLock<HandlerTable>,
+}
+
+impl LocalIC {
+    /// Bit in the IRQ source register that signals a pending GPU (peripheral) interrupt.
+    const GPU_IRQ: usize = 8;
+
+    const NAME: &'static str = "BCM Local Interrupt Controller";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
+        Self {
+            phys_mmio_start_addr,
+            registers: IRQSafeSpinLock::new(Registers::new(phys_mmio_start_addr)),
+            handler_table: InitStateLock::new([None; LocalIRQ::MAX_INCLUSIVE + 1]),
+        }
+    }
+
+    /// Map the registers and route all GPU interrupts to the boot core.
+    pub fn init(&self) -> Result<(), &'static str> {
+        let core: u32 = cpu::smp::core_id();
+        let registers = Registers::map(Self::NAME, self.phys_mmio_start_addr)?;
+
+        self.registers.lock(|regs| {
+            *regs = registers;
+
+            regs.GPU_INT_ROUTING
+                .write(GPU_INT_ROUTING::IRQ.val(core) + GPU_INT_ROUTING::FIQ.val(core))
+        });
+
+        Ok(())
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl LocalIC {
+    pub fn register_handler(
+        &self,
+        descriptor: IRQHandlerDescriptor<LocalIRQ>,
+    ) -> Result<(), &'static str> {
+        let irq_number = descriptor.number().get();
+
+        if irq_number == Self::GPU_IRQ {
+            return Err("Local IRQ 8 is reserved for the peripheral IC");
+        }
+
+        self.handler_table.write(|table| {
+            if table[irq_number].is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            table[irq_number] = Some(descriptor);
+
+            Ok(())
+        })
+    }
+
+    /// Enable a local IRQ for the executing core.
+    ///
+    /// Only the core timers (0..=3) and the mailboxes (4..=7) have enable bits, everything else
+    /// is always routed.
+    pub fn enable(&self, irq: &LocalIRQ) {
+        let core: usize = cpu::smp::core_id();
+        let irq_number = irq.get();
+
+        self.registers.lock(|regs| match irq_number {
+            0..=3 => {
+                let reg = &regs.CORE_TIMER_INT_CONTROL[core];
+                reg.set(reg.get() | (1 << irq_number));
+            }
+            4..=7 => {
+                let reg = &regs.CORE_MAILBOX_INT_CONTROL[core];
+                reg.set(reg.get() | (1 << (irq_number - 4)));
+            }
+            _ => (),
+        });
+    }
+
+    /// Dispatch pending local IRQs. Returns true if the GPU side has IRQs pending as well.
+    pub fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) -> bool {
+        let core: usize = cpu::smp::core_id();
+        let source = self.registers.lock(|regs| regs.CORE_IRQ_SOURCE[core].get());
+
+        // Bits above 11 are not IRQ sources, and the GPU bit is handled by the caller.
+        let valid_mask: u64 = (1 << (LocalIRQ::MAX_INCLUSIVE + 1)) - 1;
+        let local_mask = u64::from(source) & valid_mask & !(1 << Self::GPU_IRQ);
+
+        self.handler_table.read(|table| {
+            for irq_number in PendingIRQs::new(local_mask) {
+                match table[irq_number] {
+                    None => panic!("No handler registered for local IRQ {}", irq_number),
+                    Some(descriptor) => {
+                        // Call the IRQ handler. Panics on failure.
+                        descriptor.handler().handle().expect("Error handling IRQ");
+                    }
+                }
+            }
+        });
+
+        source & (1 << Self::GPU_IRQ) != 0
+    }
+
+    pub fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Local handler:");
+
+        self.handler_table.read(|table| {
+            for (i, opt) in table.iter().enumerate() {
+                if let Some(handler) = opt {
+                    info!("            {: >3}. {}", i, handler.name());
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,176 @@
+//! Peripheral Interrupt Controller Driver.
+//!
+//! Based on the BCM2837 TRM, section 7.5.
+
+use super::{PendingIRQs, PeripheralIRQ};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    exception::{self, asynchronous::IRQHandlerDescriptor},
+    synchronization::{self, IRQSafeSpinLock, InitStateLock},
+};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, WriteOnly},
+};
+
+register_structs! {
+    #[allow(non_snake_case)]
+    WORegisterBlock {
+        (0x00 => _reserved1),
+        (0x10 => ENABLE_1: WriteOnly<u32>),
+        (0x14 => ENABLE_2: WriteOnly<u32>),
+        (0x18 => _reserved2),
+        (0x1C => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RORegisterBlock {
+        (0x00 => BASIC_PENDING: ReadOnly<u32>),
+        (0x04 => PENDING_1: ReadOnly<u32>),
+        (0x08 => PENDING_2: ReadOnly<u32>),
+        (0x0c => @END),
+    }
+}
+
+/// Abstraction for the WriteOnly parts of the associated MMIO registers.
+type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
+
+/// Abstraction for the ReadOnly parts of the associated MMIO registers.
+type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
+
+type HandlerTable = [Option<IRQHandlerDescriptor<PeripheralIRQ>>; PeripheralIRQ::MAX_INCLUSIVE + 1];
+
+/// Representation of the peripheral interrupt controller.
+pub struct PeripheralIC {
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
+
+    /// Access to write registers is guarded with a lock.
+    wo_registers: IRQSafeSpinLock<WriteOnlyRegisters>,
+
+    /// Register read access is unguarded. The lock only covers the mapping at init.
+    ro_registers: InitStateLock<ReadOnlyRegisters>,
+
+    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
+    handler_table: InitStateLock<HandlerTable>,
+}
+
+impl PeripheralIC {
+    const NAME: &'static str = "BCM Peripheral Interrupt Controller";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
+        Self {
+            phys_mmio_start_addr,
+            wo_registers: IRQSafeSpinLock::new(WriteOnlyRegisters::new(phys_mmio_start_addr)),
+            ro_registers: InitStateLock::new(ReadOnlyRegisters::new(phys_mmio_start_addr)),
+            handler_table: InitStateLock::new([None; PeripheralIRQ::MAX_INCLUSIVE + 1]),
+        }
+    }
+
+    /// Query the list of pending IRQs.
+    fn pending_irqs(&self) -> PendingIRQs {
+        self.ro_registers.read(|regs| {
+            // Bits 8 and 9 of the basic register say "something in pending 1/2", bits 10..=20
+            // are shortcuts for some of the GPU IRQs. Either way, nothing set means nothing to do.
+            if regs.BASIC_PENDING.get() == 0 {
+                return PendingIRQs::new(0);
+            }
+
+            let pending_mask: u64 =
+                (u64::from(regs.PENDING_2.get()) << 32) | u64::from(regs.PENDING_1.get());
+
+            PendingIRQs::new(pending_mask)
+        })
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl PeripheralIC {
+    /// Map the registers.
+    pub fn init(&self) -> Result<(), &'static str> {
+        let wo_registers = WriteOnlyRegisters::map(Self::NAME, self.phys_mmio_start_addr)?;
+        let ro_registers = ReadOnlyRegisters::map(Self::NAME, self.phys_mmio_start_addr)?;
+
+        self.wo_registers.lock(|regs| *regs = wo_registers);
+        self.ro_registers.write(|regs| *regs = ro_registers);
+
+        Ok(())
+    }
+
+    pub fn register_handler(
+        &self,
+        descriptor: IRQHandlerDescriptor<PeripheralIRQ>,
+    ) -> Result<(), &'static str> {
+        self.handler_table.write(|table| {
+            let irq_number = descriptor.number().get();
+
+            if table[irq_number].is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            table[irq_number] = Some(descriptor);
+
+            Ok(())
+        })
+    }
+
+    pub fn enable(&self, irq: &PeripheralIRQ) {
+        self.wo_registers.lock(|regs| {
+            let enable_reg = if irq.get() <= 31 {
+                &regs.ENABLE_1
+            } else {
+                &regs.ENABLE_2
+            };
+
+            let enable_bit: u32 = 1 << (irq.get() % 32);
+
+            // Writing a 1 to a bit will set the corresponding IRQ enable bit. All other IRQ enable
+            // bits are unaffected. So we don't need read and OR'ing here.
+            enable_reg.set(enable_bit);
+        });
+    }
+
+    pub fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        self.handler_table.read(|table| {
+            for irq_number in self.pending_irqs() {
+                match table[irq_number] {
+                    None => panic!("No handler registered for IRQ {}", irq_number),
+                    Some(descriptor) => {
+                        // Call the IRQ handler. Panics on failure.
+                        descriptor.handler().handle().expect("Error handling IRQ");
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Peripheral handler:");
+
+        self.handler_table.read(|table| {
+            for (i, opt) in table.iter().enumerate() {
+                if let Some(handler) = opt {
+                    info!("            {: >3}. {}", i, handler.name());
+                }
+            }
+        });
+    }
+}
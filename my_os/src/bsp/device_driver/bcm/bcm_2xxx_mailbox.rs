@@ -0,0 +1,236 @@
+//! VideoCore mailbox driver.
+//!
+//! Talks to the firmware through the property channel. A message is a buffer of u32 words in
+//! memory, the mailbox only carries its address. See `property` for the messages themselves.
+//!
+//! Descriptions taken from
+//! - https://github.com/raspberrypi/firmware/wiki/Mailboxes
+//! - https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface
+
+pub mod property;
+
+pub use property::{ClockId, MemoryRange, PixelOrder, PowerDevice, PowerState};
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, drivers, memory, synchronization,
+    synchronization::SpinLock, timer,
+};
+use core::time::Duration;
+use property::{MessageBuffer, MessageBuilder, Tag};
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, WriteOnly},
+};
+
+// Mailbox registers.
+register_bitfields! {
+    u32,
+
+    /// Mailbox status.
+    STATUS [
+        /// No room to write another message.
+        FULL  OFFSET(31) NUMBITS(1) [],
+
+        /// Nothing to read.
+        EMPTY OFFSET(30) NUMBITS(1) []
+    ]
+}
+
+// Mailbox 0 is read by the ARM, mailbox 1 written.
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => READ: ReadOnly<u32>),
+        (0x04 => _reserved1),
+        (0x18 => STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x1C => _reserved2),
+        (0x20 => WRITE: WriteOnly<u32>),
+        (0x24 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The property channel, ARM to VideoCore.
+const CHANNEL_PROPERTY: u32 = 8;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+struct MailboxInner {
+    registers: Registers,
+    buffer: MessageBuffer,
+}
+
+/// Representation of the VideoCore mailbox.
+pub struct Mailbox {
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
+    inner: SpinLock<MailboxInner>,
+}
+
+impl MailboxInner {
+    const fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: unsafe { Registers::new(mmio_start_addr) },
+            buffer: MessageBuffer::new(),
+        }
+    }
+
+    /// Send the message in the buffer on the property channel and wait for the answer, which the
+    /// firmware writes back into the same buffer.
+    fn call(&mut self) -> Result<(), &'static str> {
+        let buffer_addr = self.buffer.0.as_ptr() as usize;
+        let buffer_size = core::mem::size_of::<MessageBuffer>();
+
+        // the firmware reads and writes the buffer in memory, past the ARM's caches
+        let message = memory::virt_to_phys(buffer_addr) as u32 | CHANNEL_PROPERTY;
+        memory::clean_dcache_range(buffer_addr, buffer_size);
+
+        // the firmware answers within microseconds, if it doesn't it is not going to
+        let deadline = timer::Deadline::after(TIMEOUT);
+
+        deadline
+            .poll_until(|| !self.registers.STATUS.is_set(STATUS::FULL))
+            .map_err(|_| "Mailbox timed out accepting the request")?;
+        self.registers.WRITE.set(message);
+
+        loop {
+            deadline
+                .poll_until(|| !self.registers.STATUS.is_set(STATUS::EMPTY))
+                .map_err(|_| "Mailbox timed out answering the request")?;
+
+            // answers to other channels are not ours
+            if self.registers.READ.get() == message {
+                break;
+            }
+        }
+
+        memory::invalidate_dcache_range(buffer_addr, buffer_size);
+
+        if !self.buffer.is_success() {
+            return Err("Mailbox request failed");
+        }
+
+        Ok(())
+    }
+
+    fn message<S, R>(
+        &mut self,
+        build: impl FnOnce(&mut MessageBuilder) -> Result<S, &'static str>,
+        decode: impl FnOnce(S, &MessageBuffer) -> Result<R, &'static str>,
+    ) -> Result<R, &'static str> {
+        let mut builder = MessageBuilder::new(&mut self.buffer);
+        let slots = build(&mut builder)?;
+        builder.finish();
+
+        self.call()?;
+
+        decode(slots, &self.buffer)
+    }
+}
+
+impl Mailbox {
+    pub const COMPATIBLE: &'static str = "BCM VideoCore Mailbox";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
+        Self {
+            phys_mmio_start_addr,
+            inner: SpinLock::new(MailboxInner::new(phys_mmio_start_addr)),
+        }
+    }
+
+    /// Send a message with the tags `build` adds. `decode` gets whatever `build` returned, usually
+    /// the tags' slots, to pick the responses out of the answered message.
+    pub fn message<S, R>(
+        &self,
+        build: impl FnOnce(&mut MessageBuilder) -> Result<S, &'static str>,
+        decode: impl FnOnce(S, &MessageBuffer) -> Result<R, &'static str>,
+    ) -> Result<R, &'static str> {
+        self.inner.lock(|inner| inner.message(build, decode))
+    }
+
+    /// Send a message with the single tag `tag` and return its response.
+    pub fn property<T: Tag>(&self, tag: &T) -> Result<T::Response, &'static str> {
+        self.message(|msg| msg.add(tag), |slot, buffer| slot.response(buffer))
+    }
+
+    pub fn board_revision(&self) -> Result<u32, &'static str> {
+        self.property(&property::GetBoardRevision)
+    }
+
+    #[allow(dead_code)]
+    pub fn board_serial(&self) -> Result<u64, &'static str> {
+        self.property(&property::GetBoardSerial)
+    }
+
+    /// The memory the firmware left to the ARM.
+    pub fn arm_memory(&self) -> Result<MemoryRange, &'static str> {
+        self.property(&property::GetArmMemory)
+    }
+
+    /// The memory the VideoCore keeps for itself.
+    pub fn vc_memory(&self) -> Result<MemoryRange, &'static str> {
+        self.property(&property::GetVcMemory)
+    }
+
+    /// The rate of `clock` in Hz.
+    pub fn clock_rate(&self, clock: ClockId) -> Result<u32, &'static str> {
+        match self.property(&property::GetClockRate(clock))? {
+            0 => Err("No such clock"),
+            rate => Ok(rate),
+        }
+    }
+
+    /// The SoC temperature in thousandths of a degree Celsius.
+    #[allow(dead_code)]
+    pub fn temperature(&self) -> Result<u32, &'static str> {
+        self.property(&property::GetTemperature)
+    }
+
+    #[allow(dead_code)]
+    pub fn power_state(&self, device: PowerDevice) -> Result<PowerState, &'static str> {
+        self.property(&property::GetPowerState(device))
+    }
+
+    /// Switch `device` on or off, waiting for it to become stable.
+    #[allow(dead_code)]
+    pub fn set_power_state(
+        &self,
+        device: PowerDevice,
+        on: bool,
+    ) -> Result<PowerState, &'static str> {
+        let state = self.property(&property::SetPowerState {
+            device,
+            on,
+            wait: true,
+        })?;
+
+        if !state.exists {
+            return Err("No such device");
+        }
+
+        Ok(state)
+    }
+}
+
+use synchronization::interface::Mutex;
+
+impl drivers::interface::DeviceDriver for Mailbox {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let registers = Registers::map(Self::COMPATIBLE, self.phys_mmio_start_addr)?;
+
+        self.inner.lock(|inner| inner.registers = registers);
+
+        Ok(())
+    }
+}
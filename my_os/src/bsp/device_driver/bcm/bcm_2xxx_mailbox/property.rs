@@ -0,0 +1,463 @@
+//! Property channel messages.
+//!
+//! A message is a size word, a request/response code, a list of tags and an end tag. Each tag is
+//! its id, the size of its value buffer, a request/response code and the value buffer, which the
+//! firmware overwrites with the response. The `MessageBuilder` lays the tags out and remembers
+//! where each one went, so that the responses can be picked out again after the call.
+
+use core::marker::PhantomData;
+
+const REQUEST: u32 = 0x0000_0000;
+const RESPONSE_SUCCESS: u32 = 0x8000_0000;
+const TAG_END: u32 = 0;
+
+/// Words in front of a tag's value buffer: id, value buffer size and code.
+const TAG_HEADER_WORDS: usize = 3;
+
+pub const BUFFER_WORDS: usize = 64;
+
+/// The message buffer. The low 4 bits of its address carry the channel number, so it has to be 16
+/// byte aligned. It is aligned to the 64 byte cache lines of the Cortex-A53/A72, which pads its
+/// size to whole lines as well. Cleaning and invalidating it around a call then never touches
+/// anything next to it.
+#[repr(C, align(64))]
+pub struct MessageBuffer(pub [u32; BUFFER_WORDS]);
+
+/// A property tag.
+pub trait Tag {
+    /// The tag id.
+    const ID: u32;
+
+    /// Size of the value buffer in words, the larger of request and response.
+    const VALUE_WORDS: usize;
+
+    /// What the firmware answers with.
+    type Response;
+
+    /// Fill in the request. `value` comes zeroed.
+    fn request(&self, _value: &mut [u32]) {}
+
+    /// Decode the response.
+    fn response(value: &[u32]) -> Self::Response;
+}
+
+/// Lays out a message in a `MessageBuffer`, tag by tag.
+pub struct MessageBuilder<'a> {
+    buffer: &'a mut MessageBuffer,
+
+    /// Words used so far.
+    len: usize,
+}
+
+/// Where a tag ended up in the message.
+pub struct TagSlot<T: Tag> {
+    offset: usize,
+    _tag: PhantomData<T>,
+}
+
+impl MessageBuffer {
+    pub const fn new() -> Self {
+        Self([0; BUFFER_WORDS])
+    }
+
+    /// Whether the firmware processed the message.
+    pub fn is_success(&self) -> bool {
+        self.0[1] == RESPONSE_SUCCESS
+    }
+}
+
+impl<'a> MessageBuilder<'a> {
+    pub fn new(buffer: &'a mut MessageBuffer) -> Self {
+        buffer.0[1] = REQUEST;
+
+        Self { buffer, len: 2 }
+    }
+
+    /// Append `tag`. The returned slot picks out its response once the message was sent.
+    pub fn add<T: Tag>(&mut self, tag: &T) -> Result<TagSlot<T>, &'static str> {
+        let tag_words = TAG_HEADER_WORDS + T::VALUE_WORDS;
+
+        // room for the end tag as well
+        if self.len + tag_words + 1 > BUFFER_WORDS {
+            return Err("Mailbox message too long");
+        }
+
+        let words = &mut self.buffer.0[self.len..self.len + tag_words];
+        words[0] = T::ID;
+        words[1] = (T::VALUE_WORDS * 4) as u32;
+        words[2] = REQUEST;
+        words[TAG_HEADER_WORDS..].fill(0);
+        tag.request(&mut words[TAG_HEADER_WORDS..]);
+
+        let slot = TagSlot {
+            offset: self.len,
+            _tag: PhantomData,
+        };
+        self.len += tag_words;
+
+        Ok(slot)
+    }
+
+    /// Terminate the message. Returns its size in bytes.
+    pub fn finish(self) -> usize {
+        self.buffer.0[self.len] = TAG_END;
+
+        let size = (self.len + 1) * 4;
+        self.buffer.0[0] = size as u32;
+
+        size
+    }
+}
+
+impl<T: Tag> TagSlot<T> {
+    /// Decode the tag's response from the answered message.
+    pub fn response(&self, buffer: &MessageBuffer) -> Result<T::Response, &'static str> {
+        let code = buffer.0[self.offset + 2];
+        if code & RESPONSE_SUCCESS == 0 {
+            return Err("Firmware did not answer a mailbox tag");
+        }
+
+        // the firmware says how much it wanted to write, even if that didn't fit
+        let response_bytes = (code & !RESPONSE_SUCCESS) as usize;
+        if response_bytes > T::VALUE_WORDS * 4 {
+            return Err("Mailbox tag response truncated");
+        }
+
+        let value_start = self.offset + TAG_HEADER_WORDS;
+
+        Ok(T::response(
+            &buffer.0[value_start..value_start + T::VALUE_WORDS],
+        ))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tags
+//--------------------------------------------------------------------------------------------------
+
+/// Clocks known to the firmware.
+#[allow(missing_docs)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum ClockId {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Hevc = 11,
+    Emmc2 = 12,
+    M2mc = 13,
+    PixelBvb = 14,
+}
+
+/// Devices the firmware can switch on and off.
+#[allow(missing_docs)]
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug)]
+pub enum PowerDevice {
+    SdCard = 0,
+    Uart0 = 1,
+    Uart1 = 2,
+    UsbHcd = 3,
+    I2c0 = 4,
+    I2c1 = 5,
+    I2c2 = 6,
+    Spi = 7,
+    Ccp2tx = 8,
+}
+
+/// Power state of a device.
+#[derive(Copy, Clone, Debug)]
+pub struct PowerState {
+    /// The device is powered.
+    pub on: bool,
+    /// The device exists on this board.
+    pub exists: bool,
+}
+
+/// Order of the color components in a pixel.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelOrder {
+    Bgr = 0,
+    Rgb = 1,
+}
+
+/// A physical memory range, as base address and size.
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryRange {
+    pub base: usize,
+    pub size: usize,
+}
+
+pub struct GetBoardRevision;
+
+pub struct GetBoardSerial;
+
+pub struct GetArmMemory;
+
+pub struct GetVcMemory;
+
+pub struct GetClockRate(pub ClockId);
+
+pub struct GetTemperature;
+
+pub struct GetPowerState(pub PowerDevice);
+
+pub struct SetPhysicalSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct SetVirtualSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct SetVirtualOffset {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Bits per pixel.
+pub struct SetDepth(pub u32);
+
+pub struct SetPixelOrder(pub PixelOrder);
+
+/// Allocate the framebuffer, aligned to the given number of bytes. Has to come after the tags
+/// setting up the screen in the same message.
+pub struct AllocateBuffer {
+    pub alignment: u32,
+}
+
+/// Bytes per line of the framebuffer.
+pub struct GetPitch;
+
+pub struct SetPowerState {
+    pub device: PowerDevice,
+    pub on: bool,
+    /// Let the firmware wait until the device is stable.
+    pub wait: bool,
+}
+
+impl MemoryRange {
+    fn from_words(value: &[u32]) -> Self {
+        Self {
+            base: value[0] as usize,
+            size: value[1] as usize,
+        }
+    }
+}
+
+impl PowerState {
+    fn from_word(word: u32) -> Self {
+        Self {
+            on: word & 0b01 != 0,
+            exists: word & 0b10 == 0,
+        }
+    }
+}
+
+impl Tag for GetBoardRevision {
+    const ID: u32 = 0x0001_0002;
+    const VALUE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl Tag for GetBoardSerial {
+    const ID: u32 = 0x0001_0004;
+    const VALUE_WORDS: usize = 2;
+    type Response = u64;
+
+    fn response(value: &[u32]) -> u64 {
+        (u64::from(value[1]) << 32) | u64::from(value[0])
+    }
+}
+
+impl Tag for GetArmMemory {
+    const ID: u32 = 0x0001_0005;
+    const VALUE_WORDS: usize = 2;
+    type Response = MemoryRange;
+
+    fn response(value: &[u32]) -> MemoryRange {
+        MemoryRange::from_words(value)
+    }
+}
+
+impl Tag for GetVcMemory {
+    const ID: u32 = 0x0001_0006;
+    const VALUE_WORDS: usize = 2;
+    type Response = MemoryRange;
+
+    fn response(value: &[u32]) -> MemoryRange {
+        MemoryRange::from_words(value)
+    }
+}
+
+impl Tag for GetClockRate {
+    const ID: u32 = 0x0003_0002;
+    const VALUE_WORDS: usize = 2;
+    /// The rate in Hz, 0 if the clock doesn't exist.
+    type Response = u32;
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+impl Tag for GetTemperature {
+    const ID: u32 = 0x0003_0006;
+    const VALUE_WORDS: usize = 2;
+    /// The SoC temperature in thousandths of a degree Celsius.
+    type Response = u32;
+
+    fn response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+impl Tag for GetPowerState {
+    const ID: u32 = 0x0002_0001;
+    const VALUE_WORDS: usize = 2;
+    type Response = PowerState;
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn response(value: &[u32]) -> PowerState {
+        PowerState::from_word(value[1])
+    }
+}
+
+impl Tag for SetPowerState {
+    const ID: u32 = 0x0002_8001;
+    const VALUE_WORDS: usize = 2;
+    type Response = PowerState;
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.device as u32;
+        value[1] = u32::from(self.on) | (u32::from(self.wait) << 1);
+    }
+
+    fn response(value: &[u32]) -> PowerState {
+        PowerState::from_word(value[1])
+    }
+}
+
+impl Tag for SetPhysicalSize {
+    const ID: u32 = 0x0004_8003;
+    const VALUE_WORDS: usize = 2;
+    /// Width and height the firmware settled on.
+    type Response = (u32, u32);
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.width;
+        value[1] = self.height;
+    }
+
+    fn response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl Tag for SetVirtualSize {
+    const ID: u32 = 0x0004_8004;
+    const VALUE_WORDS: usize = 2;
+    /// Width and height the firmware settled on.
+    type Response = (u32, u32);
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.width;
+        value[1] = self.height;
+    }
+
+    fn response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl Tag for SetVirtualOffset {
+    const ID: u32 = 0x0004_8009;
+    const VALUE_WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.x;
+        value[1] = self.y;
+    }
+
+    fn response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl Tag for SetDepth {
+    const ID: u32 = 0x0004_8005;
+    const VALUE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.0;
+    }
+
+    fn response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl Tag for SetPixelOrder {
+    const ID: u32 = 0x0004_8006;
+    const VALUE_WORDS: usize = 1;
+    type Response = PixelOrder;
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn response(value: &[u32]) -> PixelOrder {
+        match value[0] {
+            0 => PixelOrder::Bgr,
+            _ => PixelOrder::Rgb,
+        }
+    }
+}
+
+impl Tag for AllocateBuffer {
+    const ID: u32 = 0x0004_0001;
+    const VALUE_WORDS: usize = 2;
+    /// The framebuffer, as a VideoCore bus address.
+    type Response = MemoryRange;
+
+    fn request(&self, value: &mut [u32]) {
+        value[0] = self.alignment;
+    }
+
+    fn response(value: &[u32]) -> MemoryRange {
+        MemoryRange::from_words(value)
+    }
+}
+
+impl Tag for GetPitch {
+    const ID: u32 = 0x0004_0008;
+    const VALUE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
@@ -1,19 +1,22 @@
 //! PL011 UART driver.
+//!
+//! Comes up as 8N1 at 230400 baud, see `UartConfig::DEFAULT`. Baud rate and line settings can be
+//! changed at runtime with `PL011Uart::configure()`.
 
-/// UART settings
-/// data_bits: 8
-/// parity: even
-/// stop_bit: 1
-/// This results in 8N1 and 230400 baud.
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
-    console, cpu, drivers,
-    synchronization::{self, SpinLock},
+    common::RingBuffer,
+    console, cpu, drivers, exception, memory,
+    synchronization::{self, IRQSafeSpinLock},
 };
 // use core::fmt::{self, Write};
-use core::fmt::{self};
+use core::{
+    fmt::{self},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    fields::FieldValue,
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
     registers::{ReadOnly, ReadWrite, WriteOnly},
 };
@@ -24,6 +27,17 @@ use tock_registers::{
 register_bitfields! {
     u32,
 
+    /// Data Register.
+    DR [
+        /// Overrun error. This bit is set to 1 if data is received and the receive FIFO is already
+        /// full. The FIFO contents remain valid because no more data is written when the FIFO is
+        /// full, only the contents of the shift register are overwritten.
+        OE OFFSET(11) NUMBITS(1) [],
+
+        /// Receive (read) data character. Transmit (write) data character.
+        DATA OFFSET(0) NUMBITS(8) []
+    ],
+
     /// Flag Register.
     FR [
         /// Transmit FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
@@ -90,11 +104,45 @@ register_bitfields! {
         FEN  OFFSET(4) NUMBITS(1) [
             FifosDisabled = 0,
             FifosEnabled = 1
+        ],
+
+        /// Two stop bits select. If this bit is set to 1, two stop bits are transmitted at the end
+        /// of the frame. The receive logic does not check for two stop bits being received.
+        STP2 OFFSET(3) NUMBITS(1) [
+            OneStopBit = 0,
+            TwoStopBits = 1
+        ],
+
+        /// Even parity select. Controls the type of parity the UART uses during transmission and
+        /// reception. Has no effect if parity is disabled by the PEN bit.
+        EPS OFFSET(2) NUMBITS(1) [
+            OddParity = 0,
+            EvenParity = 1
+        ],
+
+        /// Parity enable. If this bit is set to 1, parity checking and generation is enabled.
+        PEN OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
         ]
     ],
 
     /// Control Register.
     CR [
+        /// CTS hardware flow control enable. If this bit is set to 1, data is only transmitted
+        /// when the nUARTCTS signal is asserted.
+        CTSEN OFFSET(15) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// RTS hardware flow control enable. If this bit is set to 1, data is only requested when
+        /// there is space in the receive FIFO for it to be received.
+        RTSEN OFFSET(14) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
         /// Receive enable. If this bit is set to 1, the receive section of the UART is enabled.
         /// Data reception occurs for either UART signals or SIR signals depending on the setting of
         /// the SIREN bit. When the UART is disabled in the middle of reception, it completes the
@@ -128,6 +176,75 @@ register_bitfields! {
         ]
     ],
 
+    /// Interrupt FIFO Level Select Register.
+    IFLS [
+        /// Receive interrupt FIFO level select. The trigger points for the receive interrupt are as
+        /// follows.
+        RXIFLSEL OFFSET(3) NUMBITS(5) [
+            OneEigth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEights = 0b100
+        ],
+
+        /// Transmit interrupt FIFO level select. The trigger points for the transmit interrupt are
+        /// as follows.
+        TXIFLSEL OFFSET(0) NUMBITS(3) [
+            OneEigth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEights = 0b100
+        ]
+    ],
+
+    /// Interrupt Mask Set/Clear Register.
+    IMSC [
+        /// Receive timeout interrupt mask. A read returns the current mask for the UARTRTINTR
+        /// interrupt.
+        ///
+        /// - On a write of 1, the mask of the UARTRTINTR interrupt is set.
+        /// - A write of 0 clears the mask.
+        RTIM OFFSET(6) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Transmit interrupt mask. A read returns the current mask for the UARTTXINTR interrupt.
+        ///
+        /// - On a write of 1, the mask of the UARTTXINTR interrupt is set.
+        /// - A write of 0 clears the mask.
+        TXIM OFFSET(5) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Receive interrupt mask. A read returns the current mask for the UARTRXINTR interrupt.
+        ///
+        /// - On a write of 1, the mask of the UARTRXINTR interrupt is set.
+        /// - A write of 0 clears the mask.
+        RXIM OFFSET(4) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Masked Interrupt Status Register.
+    MIS [
+        /// Receive timeout masked interrupt status. Returns the masked interrupt state of the
+        /// UARTRTINTR interrupt.
+        RTMIS OFFSET(6) NUMBITS(1) [],
+
+        /// Transmit masked interrupt status. Returns the masked interrupt state of the UARTTXINTR
+        /// interrupt.
+        TXMIS OFFSET(5) NUMBITS(1) [],
+
+        /// Receive masked interrupt status. Returns the masked interrupt state of the UARTRXINTR
+        /// interrupt.
+        RXMIS OFFSET(4) NUMBITS(1) []
+    ],
+
     /// Interrupt Clear Register.
     ICR [
         /// Meta field for all pending interrupts.
@@ -138,7 +255,7 @@ register_bitfields! {
 register_structs! {
     #[allow(non_snake_case)]
     pub RegisterBlock {
-        (0x00 => DR: ReadWrite<u32>),
+        (0x00 => DR: ReadWrite<u32, DR::Register>),
         (0x04 => _reserved1),
         (0x18 => FR: ReadOnly<u32, FR::Register>),
         (0x1c => _reserved2),
@@ -146,7 +263,10 @@ register_structs! {
         (0x28 => FBRD: WriteOnly<u32, FBRD::Register>),
         (0x2c => LCR_H: WriteOnly<u32, LCR_H::Register>),
         (0x30 => CR: WriteOnly<u32, CR::Register>),
-        (0x34 => _reserved3),
+        (0x34 => IFLS: ReadWrite<u32, IFLS::Register>),
+        (0x38 => IMSC: ReadWrite<u32, IMSC::Register>),
+        (0x3C => _reserved3),
+        (0x40 => MIS: ReadOnly<u32, MIS::Register>),
         (0x44 => ICR: WriteOnly<u32, ICR::Register>),
         (0x48 => @END),
     }
@@ -154,54 +274,103 @@ register_structs! {
 
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-#[derive(PartialEq)]
-enum BlockingMode {
-    Blocking,
-    NonBlocking,
+/// UART reference clock, set with `init_uart_clock` in config.txt.
+const DEFAULT_REF_CLOCK_HZ: u32 = 48_000_000;
+
+/// Size of the software RX and TX buffers, on top of the 16 byte HW FIFOs.
+const BUFFER_SIZE: usize = 1024;
+
+type Buffer = RingBuffer<u8, BUFFER_SIZE>;
+
+/// Number of data bits in a frame.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum WordLength {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+/// Parity bit, if any.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits at the end of a frame.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Hardware flow control. `RtsCts` needs the CTS and RTS lines routed to the UART by the BSP.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum FlowControl {
+    None,
+    RtsCts,
 }
 
+/// Baud rate and line settings of the UART.
+#[derive(Copy, Clone)]
+pub struct UartConfig {
+    pub baud_rate: u32,
+    pub word_length: WordLength,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+/// Divisor registers for a baud rate, `(IBRD, FBRD)`.
+struct BaudDivisor(u32, u32);
+
 // struct to interact with HW
 struct PL011UartInner {
     registers: Registers,
+    rx_buffer: Buffer,
+    tx_buffer: Buffer,
     chars_written: usize,
     chars_read: usize,
+    chars_dropped: usize,
+    /// RX IRQs are masked because the RX buffer is full.
+    rx_throttled: bool,
+    ref_clock_hz: u32,
+    config: UartConfig,
 }
 
 pub struct PL011Uart {
-    inner: SpinLock<PL011UartInner>,
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
+    /// Where `panic_write_fmt()` finds them. Kept outside the lock.
+    virt_mmio_start_addr: AtomicUsize,
+    inner: IRQSafeSpinLock<PL011UartInner>,
 }
 
 impl PL011UartInner {
-    // create an instance.
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+    // create an instance. The registers are unusable until `PL011Uart::init()` mapped them.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
         Self {
-            registers: Registers::new(mmio_start_addr),
+            registers: Registers::new(phys_mmio_start_addr),
+            rx_buffer: Buffer::new(0),
+            tx_buffer: Buffer::new(0),
             chars_written: 0,
             chars_read: 0,
+            chars_dropped: 0,
+            rx_throttled: false,
+            ref_clock_hz: DEFAULT_REF_CLOCK_HZ,
+            config: UartConfig::DEFAULT,
         }
     }
 
-    /// Set up baud rate and characteristics.
-    /// This results in 8N1 and 230400 baud.
-    ///
-    /// The calculation for the BRD is (we set the clock to 48 MHz in config.txt):
-    /// `(48_000_000 / 16) / 230400 = 13.028033`.
-    ///
-    /// This means the integer part is `13` and goes into the `IBRD`.
-    /// The fractional part is `0.020833`.
-    ///
-    /// `FBRD` calculation according to the PL011 Technical Reference Manual:
-    /// `INTEGER((0.020833 * 64) + 0.5) = 1`.
-    ///
-    /// Therefore, the generated baud rate divider is: `13 + 1/64 = 13.015625`. Which results in a
-    /// genrated baud rate of `48_000_000 / (16 * 13.015625) = 230492`.
-    ///
-    /// Error = `((230492 - 230400) / 230400) * 100 = 0.04%`.
-    pub fn init(&mut self) {
-        // const CPU_FREQ: usize = 48000000;
-        // const BAUDRATE: usize = 230400;
-        // const IBRD: usize = CPU_FREQ / 16 / BAUDRATE;
-        // const FBRD: usize =
+    /// Set up baud rate, line settings, FIFOs and IRQs. Uses the currently stored `UartConfig`.
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        let divisor = BaudDivisor::new(self.ref_clock_hz, self.config.baud_rate)?;
 
         // flush incase anything in fifos
         self.flush();
@@ -210,59 +379,149 @@ impl PL011UartInner {
         self.registers.ICR.write(ICR::ALL::CLEAR); // clear intrs
 
         // From the PL011 Technical Reference Manual:
-        // Set the baud rate, 8N1 and FIFO enabled.
-        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(13));
-        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(1));
+        // The LCR_H write is what actually latches IBRD and FBRD, so it has to come last.
+        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(divisor.0));
+        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(divisor.1));
         self.registers
             .LCR_H
-            .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
+            .write(self.config.line_control() + LCR_H::FEN::FifosEnabled);
+
+        // RX IRQ once the FIFO is 1/8 full, the RX timeout IRQ picks up anything below that.
+        // TX IRQ once the FIFO drained to 1/8, so there is time to refill it.
+        self.registers
+            .IFLS
+            .write(IFLS::RXIFLSEL::OneEigth + IFLS::TXIFLSEL::OneEigth);
+
+        // Enable RX IRQ + RX timeout IRQ. The TX IRQ is only turned on while there is something
+        // waiting in the TX buffer.
+        self.registers
+            .IMSC
+            .write(IMSC::RXIM::Enabled + IMSC::RTIM::Enabled + IMSC::TXIM::Disabled);
+        self.rx_throttled = false;
 
         // Turn the UART on.
+        let flow_control = match self.config.flow_control {
+            FlowControl::None => CR::CTSEN::Disabled + CR::RTSEN::Disabled,
+            FlowControl::RtsCts => CR::CTSEN::Enabled + CR::RTSEN::Enabled,
+        };
         self.registers
             .CR
-            .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+            .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled + flow_control);
+
+        Ok(())
+    }
+
+    /// Reprogram the UART with new settings. The old ones stay in place if `config` can not be
+    /// generated from the reference clock.
+    fn configure(&mut self, config: UartConfig) -> Result<(), &'static str> {
+        BaudDivisor::new(self.ref_clock_hz, config.baud_rate)?;
+
+        self.config = config;
+        self.init()
     }
 
     //* hehe this is like cpe 316 :)
 
+    #[inline(always)]
+    fn tx_fifo_full(&self) -> bool {
+        self.registers.FR.matches_all(FR::TXFF::SET)
+    }
+
+    #[inline(always)]
+    fn rx_fifo_empty(&self) -> bool {
+        self.registers.FR.matches_all(FR::RXFE::SET)
+    }
+
+    // move as much as possible from the TX buffer into the HW FIFO
+    fn fill_tx_fifo(&mut self) {
+        while !self.tx_fifo_full() {
+            match self.tx_buffer.pop() {
+                None => break,
+                Some(c) => self.registers.DR.set(c as u32),
+            }
+        }
+
+        // nothing left to send, stop the TX IRQ from firing
+        if self.tx_buffer.is_empty() {
+            self.registers.IMSC.modify(IMSC::TXIM::Disabled);
+        }
+    }
+
+    // move everything from the HW FIFO into the RX buffer, as long as there is room
+    fn drain_rx_fifo(&mut self) {
+        while !self.rx_fifo_empty() {
+            // Leave the rest in the FIFO and stop the RX IRQs until `read_char()` made room. Once
+            // the FIFO is full, RTS tells the other side to hold off, if flow control is on.
+            if self.rx_buffer.is_full() {
+                self.registers
+                    .IMSC
+                    .modify(IMSC::RXIM::Disabled + IMSC::RTIM::Disabled);
+                self.rx_throttled = true;
+                return;
+            }
+
+            let data = self.registers.DR.extract();
+
+            // whatever came in while the FIFO was full is gone
+            if data.is_set(DR::OE) {
+                self.chars_dropped += 1;
+            }
+
+            let _ = self.rx_buffer.push(data.read(DR::DATA) as u8);
+        }
+    }
+
+    // there is room in the RX buffer again, let the RX IRQs back in
+    fn unthrottle_rx(&mut self) {
+        if self.rx_throttled {
+            self.registers
+                .IMSC
+                .modify(IMSC::RXIM::Enabled + IMSC::RTIM::Enabled);
+            self.rx_throttled = false;
+        }
+    }
+
     fn write_char(&mut self, c: char) {
-        // sping waiting
-        while self.registers.FR.matches_all(FR::TXFF::SET) {
-            cpu::nop();
+        // fast path, nothing queued up and room in the FIFO
+        if self.tx_buffer.is_empty() && !self.tx_fifo_full() {
+            self.registers.DR.set(c as u32);
+            self.chars_written += 1;
+            return;
+        }
+
+        // buffer is full. IRQs are masked while we hold the lock, so make room ourselves
+        while self.tx_buffer.is_full() {
+            while self.tx_fifo_full() {
+                cpu::nop();
+            }
+            self.fill_tx_fifo();
         }
 
-        // Write the character to the buffer.
-        self.registers.DR.set(c as u32);
+        // the FIFO is above its trigger level, so the TX IRQ will fire once it drains
+        let _ = self.tx_buffer.push(c as u8);
+        self.registers.IMSC.modify(IMSC::TXIM::Enabled);
 
         self.chars_written += 1;
     }
 
-    // wait til fifo is clear
-    fn flush(&self) {
+    // send everything that is buffered and wait til fifo is clear
+    fn flush(&mut self) {
+        while !self.tx_buffer.is_empty() {
+            self.fill_tx_fifo();
+        }
+
         while self.registers.FR.matches_all(FR::BUSY::SET) {
             cpu::nop();
         }
     }
 
-    // read a character.
-    // * Option means will either return a char or None
-    fn read_char(&mut self, blocking_mode: BlockingMode) -> Option<char> {
-        // if there is no chars waiting
-        if self.registers.FR.matches_all(FR::RXFE::SET) {
-            match blocking_mode {
-                BlockingMode::NonBlocking => {
-                    return None;
-                }
-                BlockingMode::Blocking => {
-                    while self.registers.FR.matches_all(FR::RXFE::SET) {
-                        cpu::nop();
-                    }
-                }
-            }
-        }
+    // read a character, `None` if there is none waiting
+    fn read_char(&mut self) -> Option<char> {
+        // pick up anything the IRQ handler did not get to yet
+        self.drain_rx_fifo();
 
         // Read one character.
-        let ret = self.registers.DR.get() as u8 as char;
+        let ret = self.rx_buffer.pop()? as char;
         // println!(" ret {} -> {:#04x}", ret, ret as i32);
 
         // Convert carrige return to newline.
@@ -271,9 +530,112 @@ impl PL011UartInner {
         // }
 
         self.chars_read += 1;
+        self.unthrottle_rx();
 
         Some(ret)
     }
+
+    fn clear_rx(&mut self) {
+        self.drain_rx_fifo();
+        self.rx_buffer.clear();
+        self.unthrottle_rx();
+    }
+}
+
+impl UartConfig {
+    /// 8N1 at 230400 baud, no flow control.
+    pub const DEFAULT: Self = Self {
+        baud_rate: 230_400,
+        word_length: WordLength::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        flow_control: FlowControl::None,
+    };
+
+    fn line_control(&self) -> FieldValue<u32, LCR_H::Register> {
+        let wlen = match self.word_length {
+            WordLength::Five => LCR_H::WLEN::FiveBit,
+            WordLength::Six => LCR_H::WLEN::SixBit,
+            WordLength::Seven => LCR_H::WLEN::SevenBit,
+            WordLength::Eight => LCR_H::WLEN::EightBit,
+        };
+
+        let parity = match self.parity {
+            Parity::None => LCR_H::PEN::Disabled,
+            Parity::Even => LCR_H::PEN::Enabled + LCR_H::EPS::EvenParity,
+            Parity::Odd => LCR_H::PEN::Enabled + LCR_H::EPS::OddParity,
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => LCR_H::STP2::OneStopBit,
+            StopBits::Two => LCR_H::STP2::TwoStopBits,
+        };
+
+        wlen + parity + stop_bits
+    }
+}
+
+impl fmt::Display for UartConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let data_bits = match self.word_length {
+            WordLength::Five => 5,
+            WordLength::Six => 6,
+            WordLength::Seven => 7,
+            WordLength::Eight => 8,
+        };
+
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+
+        write!(
+            f,
+            "{} baud {}{}{}",
+            self.baud_rate, data_bits, parity, stop_bits
+        )?;
+
+        if self.flow_control == FlowControl::RtsCts {
+            write!(f, " RTS/CTS")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl BaudDivisor {
+    /// Calculate the divisor for `baud_rate` from the UART reference clock.
+    ///
+    /// The divider is `ref_clock / (16 * baud_rate)`. Its integer part goes into `IBRD` and the
+    /// fractional part, in 64ths, into `FBRD`. With everything scaled by 64 that is
+    /// `(4 * ref_clock) / baud_rate`, rounded to the nearest 64th.
+    ///
+    /// E.g. 48 MHz and 230400 baud: `4 * 48_000_000 / 230400 = 833.33`, so `IBRD = 833 / 64 = 13`
+    /// and `FBRD = 833 % 64 = 1`. That generates `48_000_000 / (16 * 13.015625) = 230492` baud, an
+    /// error of 0.04%.
+    fn new(ref_clock_hz: u32, baud_rate: u32) -> Result<Self, &'static str> {
+        if baud_rate == 0 {
+            return Err("Baud rate must not be 0");
+        }
+
+        let baud_rate = u64::from(baud_rate);
+        let div_x64 = (4 * u64::from(ref_clock_hz) + baud_rate / 2) / baud_rate;
+        let ibrd = div_x64 >> 6;
+        let fbrd = div_x64 & 0x3F;
+
+        // IBRD is 16 bits wide, and a zero divisor is not allowed.
+        if ibrd == 0 || ibrd > 0xFFFF {
+            return Err("Baud rate not reachable with this reference clock");
+        }
+
+        Ok(Self(ibrd as u32, fbrd as u32))
+    }
 }
 
 impl fmt::Write for PL011UartInner {
@@ -286,15 +648,86 @@ impl fmt::Write for PL011UartInner {
     }
 }
 
+// writes straight into the HW FIFO, for the panic handler
+struct PanicWriter {
+    registers: Registers,
+}
+
+impl fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            while self.registers.FR.matches_all(FR::TXFF::SET) {
+                cpu::nop();
+            }
+
+            self.registers.DR.set(c as u32);
+        }
+
+        Ok(())
+    }
+}
+
 impl PL011Uart {
     pub const COMPATIBLE: &'static str = "BCM PL011 UART"; //? what is this for?
 
-    // Create an instance.
-    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+    // Create an instance. Takes the physical address of the registers.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
         Self {
-            inner: SpinLock::new(PL011UartInner::new(mmio_start_addr)),
+            phys_mmio_start_addr,
+            virt_mmio_start_addr: AtomicUsize::new(memory::boot_mmio_phys_to_virt(
+                phys_mmio_start_addr,
+            )),
+            inner: IRQSafeSpinLock::new(PL011UartInner::new(phys_mmio_start_addr)),
         }
     }
+
+    /// Map the registers into the MMIO remap window for `panic_write_fmt()`, ahead of `init()`.
+    /// Until then it goes through the early boot tables, so this has to happen right after the
+    /// kernel's own tables took over.
+    pub fn map_panic_registers(&self) -> Result<(), &'static str> {
+        let registers = Registers::map(Self::COMPATIBLE, self.phys_mmio_start_addr)?;
+
+        self.virt_mmio_start_addr.store(
+            &*registers as *const RegisterBlock as usize,
+            Ordering::Release,
+        );
+
+        Ok(())
+    }
+
+    /// Write `args` straight to the HW FIFO, past the lock and whatever still sits in the TX
+    /// buffer. Works before `init()` as well, with whatever settings the firmware left the UART
+    /// in.
+    ///
+    /// # Safety
+    ///
+    /// - For the panic handler only. The lock may be held by the code it interrupted, so this races
+    ///   with anyone else using the UART.
+    pub unsafe fn panic_write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        let virt_addr = self.virt_mmio_start_addr.load(Ordering::Acquire);
+        let mut writer = PanicWriter {
+            registers: Registers::new(virt_addr),
+        };
+
+        fmt::Write::write_fmt(&mut writer, args)
+    }
+
+    /// Apply new baud rate and line settings. Anything still waiting to be sent goes out with the
+    /// old settings first.
+    pub fn configure(&self, config: UartConfig) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.configure(config))
+    }
+
+    /// Return the current settings.
+    pub fn config(&self) -> UartConfig {
+        self.inner.lock(|inner| inner.config)
+    }
+
+    /// Tell the driver the UART reference clock rate, as reported by the firmware. The baud rate
+    /// divisor is only recalculated with the next `configure()`.
+    pub fn set_reference_clock(&self, ref_clock_hz: u32) {
+        self.inner.lock(|inner| inner.ref_clock_hz = ref_clock_hz)
+    }
 }
 
 //* for the OS
@@ -307,7 +740,25 @@ impl drivers::interface::DeviceDriver for PL011Uart {
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
-        self.inner.lock(|inner| inner.init());
+        // same mapping as the one for panics
+        let registers = Registers::map(Self::COMPATIBLE, self.phys_mmio_start_addr)?;
+
+        self.inner.lock(|inner| {
+            inner.registers = registers;
+            inner.init()
+        })
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &exception::asynchronous::IRQNumber,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
 
         Ok(())
     }
@@ -331,17 +782,44 @@ impl console::interface::Write for PL011Uart {
 
 impl console::interface::Read for PL011Uart {
     fn read_char(&self) -> char {
-        self.inner
-            .lock(|inner| inner.read_char(BlockingMode::Blocking).unwrap())
+        // IRQs are off, e.g. early boot. nothing will wake us up, so just poll, without holding
+        // the lock in between
+        if exception::asynchronous::is_local_irq_masked() {
+            loop {
+                if let Some(c) = self.inner.lock(|inner| inner.read_char()) {
+                    return c;
+                }
+
+                cpu::nop();
+            }
+        }
+
+        // Sleep until the next IRQ whenever the buffer is empty. The check and the `wfi` both run
+        // with IRQs masked, so a char arriving in between still wakes us up. The lock is only held
+        // for the check though, so other cores can go on printing while this one sleeps. The IRQ
+        // handler runs as soon as the mask is lifted.
+        loop {
+            let c = exception::asynchronous::exec_with_irq_masked(|| {
+                let c = self.inner.lock(|inner| inner.read_char());
+                if c.is_none() {
+                    cpu::wfi();
+                }
+
+                c
+            });
+
+            if let Some(c) = c {
+                return c;
+            }
+        }
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        self.inner.lock(|inner| inner.read_char())
     }
 
     fn clear_rx(&self) {
-        // Read from the RX FIFO until it is indicating empty.
-        while self
-            .inner
-            .lock(|inner| inner.read_char(BlockingMode::NonBlocking))
-            .is_some()
-        {}
+        self.inner.lock(|inner| inner.clear_rx());
     }
 }
 
@@ -353,6 +831,33 @@ impl console::interface::Stats for PL011Uart {
     fn chars_read(&self) -> usize {
         self.inner.lock(|inner| inner.chars_read)
     }
+
+    fn chars_dropped(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_dropped)
+    }
 }
 
 impl console::interface::All for PL011Uart {}
+
+impl exception::asynchronous::interface::IRQHandler for PL011Uart {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            let pending = inner.registers.MIS.extract();
+
+            // Clear all pending IRQs.
+            inner.registers.ICR.write(ICR::ALL::CLEAR);
+
+            // Check for any kind of RX interrupt.
+            if pending.is_set(MIS::RXMIS) || pending.is_set(MIS::RTMIS) {
+                inner.drain_rx_fifo();
+            }
+
+            // Room in the TX FIFO again.
+            if pending.is_set(MIS::TXMIS) {
+                inner.fill_tx_fifo();
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! BCM System Timer Driver.
+//!
+//! A free-running 64 bit counter at 1 MHz, clocked independently of the ARM cores, with four
+//! compare channels that raise an IRQ when the low 32 bits of the counter match them. Channels 0
+//! and 2 belong to the VideoCore firmware, 1 and 3 are free for the ARM.
+//!
+//! Descriptions taken from the "BCM2835 ARM Peripherals" document, chapter 12.
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, drivers, synchronization,
+    synchronization::IRQSafeSpinLock, timer,
+};
+use core::num::NonZeroU64;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        /// Control/Status. Bit n is set once channel n matched, writing 1 clears it.
+        (0x00 => CS: ReadWrite<u32>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0C => C: [ReadWrite<u32>; 4]),
+        (0x1C => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+struct SystemTimerInner {
+    registers: Registers,
+}
+
+/// Representation of the system timer.
+pub struct SystemTimer {
+    /// Where the registers are, they get mapped at init.
+    phys_mmio_start_addr: usize,
+    inner: IRQSafeSpinLock<SystemTimerInner>,
+}
+
+impl SystemTimerInner {
+    const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    fn ticks(&self) -> u64 {
+        // CLO can wrap between reading the two halves, in which case CHI changes too
+        loop {
+            let hi = self.registers.CHI.get();
+            let lo = self.registers.CLO.get();
+
+            if self.registers.CHI.get() == hi {
+                return (u64::from(hi) << 32) | u64::from(lo);
+            }
+        }
+    }
+}
+
+impl SystemTimer {
+    pub const COMPATIBLE: &'static str = "BCM System Timer";
+
+    /// The counter's frequency.
+    pub const FREQUENCY: NonZeroU64 = NonZeroU64::new(1_000_000).unwrap();
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(phys_mmio_start_addr: usize) -> Self {
+        Self {
+            phys_mmio_start_addr,
+            inner: IRQSafeSpinLock::new(SystemTimerInner::new(phys_mmio_start_addr)),
+        }
+    }
+
+    fn check_channel(channel: usize) -> Result<(), &'static str> {
+        match channel {
+            1 | 3 => Ok(()),
+            0 | 2 => Err("Compare channel belongs to the VideoCore"),
+            _ => Err("No such compare channel"),
+        }
+    }
+
+    /// Arm compare `channel` to match once the low 32 bits of the counter reach `ticks`. Clears a
+    /// previous match.
+    #[allow(dead_code)]
+    pub fn set_compare(&self, channel: usize, ticks: u32) -> Result<(), &'static str> {
+        Self::check_channel(channel)?;
+
+        self.inner.lock(|inner| {
+            inner.registers.C[channel].set(ticks);
+            inner.registers.CS.set(1 << channel);
+        });
+
+        Ok(())
+    }
+
+    /// Whether compare `channel` matched since it was last cleared.
+    #[allow(dead_code)]
+    pub fn compare_matched(&self, channel: usize) -> Result<bool, &'static str> {
+        Self::check_channel(channel)?;
+
+        Ok(self
+            .inner
+            .lock(|inner| inner.registers.CS.get() & (1 << channel) != 0))
+    }
+
+    /// Clear a match of compare `channel`, which also drops its IRQ.
+    #[allow(dead_code)]
+    pub fn clear_compare(&self, channel: usize) -> Result<(), &'static str> {
+        Self::check_channel(channel)?;
+
+        self.inner
+            .lock(|inner| inner.registers.CS.set(1 << channel));
+
+        Ok(())
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::Mutex;
+
+impl drivers::interface::DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        let registers = Registers::map(Self::COMPATIBLE, self.phys_mmio_start_addr)?;
+
+        self.inner.lock(|inner| inner.registers = registers);
+
+        Ok(())
+    }
+}
+
+impl timer::interface::ClockSource for SystemTimer {
+    fn name(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    fn frequency(&self) -> NonZeroU64 {
+        Self::FREQUENCY
+    }
+
+    fn ticks(&self) -> u64 {
+        self.inner.lock(|inner| inner.ticks())
+    }
+}
@@ -1,6 +1,11 @@
 //! Common device driver code.
 
-use core::{marker::PhantomData, ops};
+use crate::memory;
+use core::{
+    marker::PhantomData,
+    mem,
+    ops::{self, RangeInclusive},
+};
 
 pub struct MMIODerefWrapper<T> {
     start_addr: usize,
@@ -15,6 +20,16 @@ impl<T> MMIODerefWrapper<T> {
             phantom: PhantomData,
         }
     }
+
+    /// Map the register block at the physical `phys_start_addr` into the kernel's MMIO remap window
+    /// and return an instance for it. `name` shows up in the table of MMIO mappings.
+    pub fn map(name: &'static str, phys_start_addr: usize) -> Result<Self, &'static str> {
+        let phys_range =
+            RangeInclusive::new(phys_start_addr, phys_start_addr + mem::size_of::<T>() - 1);
+        let virt_addr = memory::mmu::kernel_map_mmio(name, &phys_range)?;
+
+        Ok(unsafe { Self::new(virt_addr) })
+    }
 }
 
 impl<T> ops::Deref for MMIODerefWrapper<T> {
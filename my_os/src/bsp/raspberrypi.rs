@@ -1,10 +1,24 @@
-//! BSP file for the Raspberry Pi 3
+//! BSP file for the Raspberry Pi 3 and 4
 
 // export board specific implementations
+pub mod board;
 pub mod cpu;
 pub mod drivers;
+pub mod exception;
 pub mod memory;
 
+/// The board this kernel is built for, when the firmware can't say what it actually runs on.
+#[cfg(feature = "bsp_rpi3")]
+const BSP_BOARD_NAME: &str = "Raspberry Pi 3";
+
+/// The board this kernel is built for, when the firmware can't say what it actually runs on.
+#[cfg(feature = "bsp_rpi4")]
+const BSP_BOARD_NAME: &str = "Raspberry Pi 4";
+
+/// The board's model, as detected at init.
 pub fn board_name() -> &'static str {
-    "Raspberry Pi 3"
+    match board::board_info() {
+        Some(info) => info.model.name(),
+        None => BSP_BOARD_NAME,
+    }
 }
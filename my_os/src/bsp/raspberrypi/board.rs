@@ -0,0 +1,254 @@
+//! Board identification.
+//!
+//! The firmware knows the board's revision code, which encodes model, SoC, RAM size, manufacturer
+//! and board revision. Only the new-style codes (bit 23 set) are decoded, the old ones were only
+//! ever used by the first Pi 1 models.
+//!
+//! Layout taken from
+//! - https://www.raspberrypi.com/documentation/computers/raspberry-pi.html#raspberry-pi-revision-codes
+
+use crate::synchronization::{self, InitStateLock};
+use core::fmt;
+
+/// Board models.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Model {
+    A,
+    B,
+    APlus,
+    BPlus,
+    TwoB,
+    Alpha,
+    CM1,
+    ThreeB,
+    Zero,
+    CM3,
+    ZeroW,
+    ThreeBPlus,
+    ThreeAPlus,
+    CM3Plus,
+    FourB,
+    ZeroTwoW,
+    FourHundred,
+    CM4,
+    CM4S,
+    Unknown(u8),
+}
+
+/// The SoC the board is built around.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Soc {
+    BCM2835,
+    BCM2836,
+    BCM2837,
+    BCM2711,
+    Unknown(u8),
+}
+
+/// Who made the board.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Manufacturer {
+    SonyUK,
+    Egoman,
+    Embest,
+    SonyJapan,
+    Stadium,
+    Unknown(u8),
+}
+
+/// What the revision code says about the board.
+#[derive(Copy, Clone)]
+pub struct BoardInfo {
+    /// The raw revision code.
+    pub revision_code: u32,
+    pub model: Model,
+    pub soc: Soc,
+    /// Size of the RAM in bytes.
+    pub ram_size: usize,
+    pub manufacturer: Manufacturer,
+    /// Board revision, the minor number of "1.x".
+    pub revision: u8,
+}
+
+/// The SoC this kernel is built for.
+#[cfg(feature = "bsp_rpi3")]
+const BSP_SOC: Soc = Soc::BCM2837;
+
+/// The SoC this kernel is built for.
+#[cfg(feature = "bsp_rpi4")]
+const BSP_SOC: Soc = Soc::BCM2711;
+
+/// Filled in during kernel init, `None` if the firmware could not tell.
+static BOARD_INFO: InitStateLock<Option<BoardInfo>> = InitStateLock::new(None);
+
+impl Model {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0x00 => Self::A,
+            0x01 => Self::B,
+            0x02 => Self::APlus,
+            0x03 => Self::BPlus,
+            0x04 => Self::TwoB,
+            0x05 => Self::Alpha,
+            0x06 => Self::CM1,
+            0x08 => Self::ThreeB,
+            0x09 => Self::Zero,
+            0x0A => Self::CM3,
+            0x0C => Self::ZeroW,
+            0x0D => Self::ThreeBPlus,
+            0x0E => Self::ThreeAPlus,
+            0x10 => Self::CM3Plus,
+            0x11 => Self::FourB,
+            0x12 => Self::ZeroTwoW,
+            0x13 => Self::FourHundred,
+            0x14 => Self::CM4,
+            0x15 => Self::CM4S,
+            x => Self::Unknown(x),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::A => "Raspberry Pi Model A",
+            Self::B => "Raspberry Pi Model B",
+            Self::APlus => "Raspberry Pi Model A+",
+            Self::BPlus => "Raspberry Pi Model B+",
+            Self::TwoB => "Raspberry Pi 2 Model B",
+            Self::Alpha => "Raspberry Pi Alpha",
+            Self::CM1 => "Raspberry Pi Compute Module 1",
+            Self::ThreeB => "Raspberry Pi 3 Model B",
+            Self::Zero => "Raspberry Pi Zero",
+            Self::CM3 => "Raspberry Pi Compute Module 3",
+            Self::ZeroW => "Raspberry Pi Zero W",
+            Self::ThreeBPlus => "Raspberry Pi 3 Model B+",
+            Self::ThreeAPlus => "Raspberry Pi 3 Model A+",
+            Self::CM3Plus => "Raspberry Pi Compute Module 3+",
+            Self::FourB => "Raspberry Pi 4 Model B",
+            Self::ZeroTwoW => "Raspberry Pi Zero 2 W",
+            Self::FourHundred => "Raspberry Pi 400",
+            Self::CM4 => "Raspberry Pi Compute Module 4",
+            Self::CM4S => "Raspberry Pi Compute Module 4S",
+            Self::Unknown(_) => "Unknown Raspberry Pi",
+        }
+    }
+}
+
+impl Soc {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0 => Self::BCM2835,
+            1 => Self::BCM2836,
+            2 => Self::BCM2837,
+            3 => Self::BCM2711,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+impl Manufacturer {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0 => Self::SonyUK,
+            1 => Self::Egoman,
+            2 | 4 => Self::Embest,
+            3 => Self::SonyJapan,
+            5 => Self::Stadium,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+impl BoardInfo {
+    /// Decode a new-style revision code.
+    pub fn decode(revision_code: u32) -> Result<Self, &'static str> {
+        let field = |shift: u32, bits: u32| ((revision_code >> shift) & ((1 << bits) - 1)) as u8;
+
+        if field(23, 1) == 0 {
+            return Err("Old-style board revision code");
+        }
+
+        // 256 MiB << n
+        let ram_size = match field(20, 3) {
+            x @ 0..=5 => (256 * 1024 * 1024) << x,
+            _ => return Err("Unknown RAM size in board revision code"),
+        };
+
+        Ok(Self {
+            revision_code,
+            model: Model::from_code(field(4, 8)),
+            soc: Soc::from_code(field(12, 4)),
+            ram_size,
+            manufacturer: Manufacturer::from_code(field(16, 4)),
+            revision: field(0, 4),
+        })
+    }
+
+    /// Whether the board has the SoC this kernel was built for.
+    pub fn matches_bsp(&self) -> bool {
+        self.soc == BSP_SOC
+    }
+}
+
+impl fmt::Display for Soc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BCM2835 => write!(f, "BCM2835"),
+            Self::BCM2836 => write!(f, "BCM2836"),
+            Self::BCM2837 => write!(f, "BCM2837"),
+            Self::BCM2711 => write!(f, "BCM2711"),
+            Self::Unknown(x) => write!(f, "unknown SoC {}", x),
+        }
+    }
+}
+
+impl fmt::Display for Manufacturer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SonyUK => write!(f, "Sony UK"),
+            Self::Egoman => write!(f, "Egoman"),
+            Self::Embest => write!(f, "Embest"),
+            Self::SonyJapan => write!(f, "Sony Japan"),
+            Self::Stadium => write!(f, "Stadium"),
+            Self::Unknown(x) => write!(f, "unknown manufacturer {}", x),
+        }
+    }
+}
+
+impl fmt::Display for BoardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rev 1.{}", self.model.name(), self.revision)?;
+
+        if let Model::Unknown(x) = self.model {
+            write!(f, " (type {:#04x})", x)?;
+        }
+
+        write!(
+            f,
+            ", {}, {} MiB RAM, made by {} (revision code {:#08x})",
+            self.soc,
+            self.ram_size >> 20,
+            self.manufacturer,
+            self.revision_code
+        )
+    }
+}
+
+use synchronization::interface::ReadWriteEx;
+
+/// Decode the board's revision code and keep the result for `board_info()`. Only during kernel
+/// init.
+pub fn init(revision_code: u32) -> Result<(), &'static str> {
+    let info = BoardInfo::decode(revision_code)?;
+
+    BOARD_INFO.write(|board_info| *board_info = Some(info));
+
+    Ok(())
+}
+
+/// What is known about the board, `None` if the firmware could not tell.
+pub fn board_info() -> Option<BoardInfo> {
+    BOARD_INFO.read(|board_info| *board_info)
+}
@@ -4,3 +4,11 @@
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
 pub static BOOT_CORE_ID: u64 = 0; // change this if i want a main core to run on
+
+/// Number of cores on the board. Has to match `__rpi_num_cores` in kernel.ld.
+pub const NUM_CORES: usize = 4;
+
+/// The firmware parks the secondary cores polling these physical addresses (the "spin table"), one
+/// per core.
+/// Writing an address there sends the core off to it.
+pub const SPIN_TABLE_RELEASE_ADDR: [usize; NUM_CORES] = [0xD8, 0xE0, 0xE8, 0xF0];
@@ -1,17 +1,48 @@
 //! BSP Memory Management.
-use super::memory::map::mmio;
-use crate::{bsp::device_driver, console, drivers as generic_driver};
-use core::sync::atomic::{AtomicBool, Ordering};
+use super::{exception::asynchronous::irq_map, memory::map::mmio};
+use crate::{
+    bsp::device_driver, console, drivers as generic_driver, exception, framebuffer, memory, timer,
+    warn,
+};
+use core::{
+    fmt,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+pub use device_driver::{FlowControl, FramebufferInfo, Parity, StopBits, UartConfig, WordLength};
+
+/// The console UART's settings, applied once the firmware told us the UART's reference clock.
+const CONSOLE_UART_CONFIG: UartConfig = UartConfig::DEFAULT;
+
+/// The screen size asked of the firmware.
+const FRAMEBUFFER_WIDTH: u32 = 1024;
+const FRAMEBUFFER_HEIGHT: u32 = 768;
 
 /// globals
 static PL011_UART: device_driver::PL011Uart =
     unsafe { device_driver::PL011Uart::new(mmio::PL011_UART_START) };
 static GPIO: device_driver::GPIO = unsafe { device_driver::GPIO::new(mmio::GPIO_START) };
+static MAILBOX: device_driver::Mailbox =
+    unsafe { device_driver::Mailbox::new(mmio::MAILBOX_START) };
+static FRAMEBUFFER: device_driver::Framebuffer =
+    device_driver::Framebuffer::new(&MAILBOX, FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT);
+static FRAMEBUFFER_CONSOLE: framebuffer::FramebufferConsole =
+    framebuffer::FramebufferConsole::new(&FRAMEBUFFER);
+static SYSTEM_TIMER: device_driver::SystemTimer =
+    unsafe { device_driver::SystemTimer::new(mmio::SYSTEM_TIMER_START) };
 
-fn post_init_uart() -> Result<(), &'static str> {
-    console::register_console(&PL011_UART);
+#[cfg(feature = "bsp_rpi3")]
+static INTERRUPT_CONTROLLER: device_driver::InterruptController = unsafe {
+    device_driver::InterruptController::new(mmio::LOCAL_IC_START, mmio::PERIPHERAL_IC_START)
+};
 
-    Ok(())
+#[cfg(feature = "bsp_rpi4")]
+static INTERRUPT_CONTROLLER: device_driver::GICv2 =
+    unsafe { device_driver::GICv2::new(mmio::GICD_START, mmio::GICC_START) };
+
+fn post_init_uart() -> Result<(), &'static str> {
+    console::register_console("uart", &PL011_UART)
 }
 
 fn post_init_gpio() -> Result<(), &'static str> {
@@ -19,22 +50,120 @@ fn post_init_gpio() -> Result<(), &'static str> {
     Ok(())
 }
 
+// the UART came up assuming the default reference clock, the firmware knows better. only with the
+// real clock does the baud rate come out right, so the console settings get applied after that
+fn post_init_mailbox() -> Result<(), &'static str> {
+    // The framebuffer is handed out of the VideoCore's memory and gets mapped non-cacheable. Its
+    // cacheable alias in the linear map has to go before that.
+    memory::mmu::kernel_unmap_dram(&vc_memory()?)?;
+
+    let uart_clock_hz = MAILBOX.clock_rate(device_driver::ClockId::Uart)?;
+    PL011_UART.set_reference_clock(uart_clock_hz);
+    configure_uart(CONSOLE_UART_CONFIG)?;
+
+    // not knowing the exact board is no reason not to boot
+    if let Err(x) = MAILBOX.board_revision().and_then(super::board::init) {
+        warn!("Could not identify the board: {}", x);
+    }
+
+    Ok(())
+}
+
+// with the `framebuffer_console` feature, the console shows up on the screen as well, starting
+// with what was printed during boot so far
+fn post_init_framebuffer() -> Result<(), &'static str> {
+    if cfg!(feature = "framebuffer_console") && FRAMEBUFFER.info().is_some() {
+        console::kernel_log().replay(&FRAMEBUFFER_CONSOLE);
+        console::register_console("framebuffer", &FRAMEBUFFER_CONSOLE)?;
+    }
+
+    Ok(())
+}
+
+fn post_init_system_timer() -> Result<(), &'static str> {
+    timer::time_manager().register_clock_source(&SYSTEM_TIMER);
+
+    Ok(())
+}
+
+fn post_init_interrupt_controller() -> Result<(), &'static str> {
+    exception::asynchronous::register_irq_manager(&INTERRUPT_CONTROLLER);
+
+    Ok(())
+}
+
 // ? what are these for?
 fn driver_uart() -> Result<(), &'static str> {
-    let uart_descriptor =
-        generic_driver::DeviceDriverDescriptor::new(&PL011_UART, Some(post_init_uart));
+    let uart_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        &PL011_UART,
+        Some(post_init_uart),
+        Some(irq_map::PL011_UART),
+    );
     generic_driver::driver_manager().register_driver(uart_descriptor);
 
     Ok(())
 }
 
 fn driver_gpio() -> Result<(), &'static str> {
-    let gpio_descriptor = generic_driver::DeviceDriverDescriptor::new(&GPIO, Some(post_init_gpio));
+    let gpio_descriptor =
+        generic_driver::DeviceDriverDescriptor::new(&GPIO, Some(post_init_gpio), None);
     generic_driver::driver_manager().register_driver(gpio_descriptor);
 
     Ok(())
 }
 
+fn driver_mailbox() -> Result<(), &'static str> {
+    let mailbox_descriptor =
+        generic_driver::DeviceDriverDescriptor::new(&MAILBOX, Some(post_init_mailbox), None);
+    generic_driver::driver_manager().register_driver(mailbox_descriptor);
+
+    Ok(())
+}
+
+fn driver_framebuffer() -> Result<(), &'static str> {
+    let framebuffer_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        &FRAMEBUFFER,
+        Some(post_init_framebuffer),
+        None,
+    );
+    generic_driver::driver_manager().register_driver(framebuffer_descriptor);
+
+    Ok(())
+}
+
+fn driver_system_timer() -> Result<(), &'static str> {
+    let system_timer_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        &SYSTEM_TIMER,
+        Some(post_init_system_timer),
+        None,
+    );
+    generic_driver::driver_manager().register_driver(system_timer_descriptor);
+
+    Ok(())
+}
+
+fn driver_interrupt_controller() -> Result<(), &'static str> {
+    let interrupt_controller_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        &INTERRUPT_CONTROLLER,
+        Some(post_init_interrupt_controller),
+        None,
+    );
+    generic_driver::driver_manager().register_driver(interrupt_controller_descriptor);
+
+    Ok(())
+}
+
+fn driver_timer() -> Result<(), &'static str> {
+    let timer_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        timer::time_manager(),
+        None,
+        Some(irq_map::CORE_TIMER_PHYS_NONSECURE),
+    );
+    generic_driver::driver_manager().register_driver(timer_descriptor);
+
+    Ok(())
+}
+
 // initialize device subsystem
 pub unsafe fn init() -> Result<(), &'static str> {
     static INIT_DONE: AtomicBool = AtomicBool::new(false);
@@ -45,7 +174,70 @@ pub unsafe fn init() -> Result<(), &'static str> {
     // if either fail, causes panic
     driver_uart()?;
     driver_gpio()?;
+    driver_mailbox()?;
+    driver_framebuffer()?;
+    driver_system_timer()?;
+    driver_interrupt_controller()?;
+    driver_timer()?;
 
     INIT_DONE.store(true, Ordering::Relaxed);
     Ok(())
 }
+
+/// Return the settings of the console UART.
+pub fn uart_config() -> UartConfig {
+    PL011_UART.config()
+}
+
+/// Reprogram the console UART. With hardware flow control, CTS and RTS get routed to GPIO 16 and 17
+/// first. Without, the pins go back to being inputs afterwards.
+pub fn configure_uart(config: UartConfig) -> Result<(), &'static str> {
+    if config.flow_control == FlowControl::RtsCts {
+        GPIO.map_pl011_uart_flow_control();
+    }
+
+    PL011_UART.configure(config)?;
+
+    if config.flow_control == FlowControl::None {
+        GPIO.unmap_pl011_uart_flow_control();
+    }
+
+    Ok(())
+}
+
+/// Map the console UART for the panic handler, see `PL011Uart::map_panic_registers()`.
+pub fn map_panic_console() -> Result<(), &'static str> {
+    PL011_UART.map_panic_registers()
+}
+
+/// Print `args` on the console UART without taking any lock.
+///
+/// # Safety
+///
+/// - Only for the panic handler, see `PL011Uart::panic_write_fmt()`.
+pub unsafe fn panic_console_write_fmt(args: fmt::Arguments) -> fmt::Result {
+    PL011_UART.panic_write_fmt(args)
+}
+
+/// What the firmware set up for the screen, `None` if there is no framebuffer.
+pub fn framebuffer_info() -> Option<FramebufferInfo> {
+    FRAMEBUFFER.info()
+}
+
+/// The physical range of DRAM the firmware left to the ARM, as reported over the mailbox.
+pub fn arm_memory() -> Result<RangeInclusive<usize>, &'static str> {
+    memory_range(MAILBOX.arm_memory()?)
+}
+
+/// The physical range of DRAM the VideoCore keeps for itself.
+pub fn vc_memory() -> Result<RangeInclusive<usize>, &'static str> {
+    memory_range(MAILBOX.vc_memory()?)
+}
+
+fn memory_range(range: device_driver::MemoryRange) -> Result<RangeInclusive<usize>, &'static str> {
+    if range.size == 0 {
+        return Err("Firmware reported an empty memory range");
+    }
+
+    Ok(RangeInclusive::new(range.base, range.base + range.size - 1))
+}
@@ -0,0 +1,3 @@
+//! BSP synchronous and asynchronous exception handling.
+
+pub mod asynchronous;
@@ -0,0 +1,43 @@
+//! BSP asynchronous exception handling.
+
+pub use crate::bsp::device_driver::IRQNumber;
+
+/// The board's IRQ numbers.
+#[cfg(feature = "bsp_rpi3")]
+#[allow(dead_code)]
+pub(in crate::bsp) mod irq_map {
+    use crate::bsp::device_driver::{IRQNumber, LocalIRQ, PeripheralIRQ};
+
+    // per core, local controller
+    pub const CORE_TIMER_PHYS_NONSECURE: IRQNumber = IRQNumber::Local(LocalIRQ::new(1));
+
+    // GPU peripherals, peripheral controller
+    pub const SYSTEM_TIMER_C1: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(1));
+    pub const SYSTEM_TIMER_C3: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(3));
+    pub const GPIO_BANK_0: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(49));
+    pub const GPIO_BANK_1: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(50));
+    pub const GPIO_BANK_2: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(51));
+    pub const GPIO_ALL_BANKS: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(52));
+    pub const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
+}
+
+/// The board's IRQ numbers.
+///
+/// VideoCore peripheral IRQ `n` is wired to GIC SPI `64 + n`, which is interrupt ID `96 + n`.
+#[cfg(feature = "bsp_rpi4")]
+#[allow(dead_code)]
+pub(in crate::bsp) mod irq_map {
+    use crate::bsp::device_driver::IRQNumber;
+
+    // per core, PPI
+    pub const CORE_TIMER_PHYS_NONSECURE: IRQNumber = IRQNumber::new(30);
+
+    // VideoCore peripherals, SPI
+    pub const SYSTEM_TIMER_C1: IRQNumber = IRQNumber::new(97);
+    pub const SYSTEM_TIMER_C3: IRQNumber = IRQNumber::new(99);
+    pub const GPIO_BANK_0: IRQNumber = IRQNumber::new(145);
+    pub const GPIO_BANK_1: IRQNumber = IRQNumber::new(146);
+    pub const GPIO_BANK_2: IRQNumber = IRQNumber::new(147);
+    pub const GPIO_ALL_BANKS: IRQNumber = IRQNumber::new(148);
+    pub const PL011_UART: IRQNumber = IRQNumber::new(153);
+}
@@ -1,18 +1,200 @@
 //! BSP Memory Management.
+//!
+//! The kernel's physical layout, set up by kernel.ld:
+//!
+//! ```text
+//! +---------------------------------------+ 0x0
+//! | armstub + spin table (firmware)       |
+//! +---------------------------------------+ __code_start == 0x8_0000
+//! | .text                                 | RX
+//! +---------------------------------------+ __rodata_start
+//! | .rodata                               | RO XN
+//! +---------------------------------------+ __data_start
+//! | .data, .bss                           | RW XN
+//! +---------------------------------------+ __heap_start == __data_end_exclusive
+//! | kernel heap                           | RW XN
+//! +---------------------------------------+ __core_stacks_start
+//! | core 0 stack guard                    | unmapped
+//! | core 0 stack                          | RW XN
+//! | ...                                   |
+//! | core 3 stack guard                    | unmapped
+//! | core 3 stack                          | RW XN
+//! +---------------------------------------+ __core_stacks_end_exclusive
+//! ```
+//!
+//! All boundaries are 64 KiB page aligned. The kernel is linked at `mmu::KERNEL_VIRT_START` plus
+//! the physical addresses above, the linker symbols are virtual addresses.
+
+pub mod mmu;
+
+use crate::memory::{self, virt_to_phys};
+use core::{cell::UnsafeCell, ops::RangeInclusive};
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __core_stacks_start: UnsafeCell<()>;
+    static __core_stacks_end_exclusive: UnsafeCell<()>;
+
+    static __code_start: UnsafeCell<()>;
+    static __code_end_exclusive: UnsafeCell<()>;
+
+    static __rodata_start: UnsafeCell<()>;
+    static __rodata_end_exclusive: UnsafeCell<()>;
+
+    static __data_start: UnsafeCell<()>;
+    static __data_end_exclusive: UnsafeCell<()>;
+
+    static __heap_start: UnsafeCell<()>;
+    static __heap_end_exclusive: UnsafeCell<()>;
+}
 
 /// The board's physical memory map.
 #[rustfmt::skip]
 pub(super) mod map {
 
+    /// The inclusive end address of the memory map.
+    ///
+    /// End address + 1 must be power of two.
+    pub const END_INCLUSIVE:       usize = 0xFFFF_FFFF;
+
+    pub const DRAM_START:          usize = 0x0000_0000;
+
+    pub const SYSTEM_TIMER_OFFSET: usize = 0x0000_3000;
+    pub const MAILBOX_OFFSET:      usize = 0x0000_B880;
     pub const GPIO_OFFSET:         usize = 0x0020_0000;
     pub const UART_OFFSET:         usize = 0x0020_1000;
 
     /// Physical devices.
+    #[cfg(feature = "bsp_rpi3")]
+    pub mod mmio {
+        use super::*;
+
+        pub const START:               usize =         0x3F00_0000;
+        pub const SYSTEM_TIMER_START:  usize = START + SYSTEM_TIMER_OFFSET;
+        pub const PERIPHERAL_IC_START: usize = START + 0x0000_B200;
+        pub const MAILBOX_START:       usize = START + MAILBOX_OFFSET;
+        pub const GPIO_START:          usize = START + GPIO_OFFSET;
+        pub const PL011_UART_START:    usize = START + UART_OFFSET;
+        pub const LOCAL_IC_START:      usize =         0x4000_0000;
+        pub const END_INCLUSIVE:       usize =         0x4003_FFFF;
+    }
+
+    /// Physical devices.
+    #[cfg(feature = "bsp_rpi4")]
     pub mod mmio {
         use super::*;
 
-        pub const START:            usize =         0x3F00_0000;
-        pub const GPIO_START:       usize = START + GPIO_OFFSET;
-        pub const PL011_UART_START: usize = START + UART_OFFSET;
+        pub const START:              usize =         0xFE00_0000;
+        pub const SYSTEM_TIMER_START: usize = START + SYSTEM_TIMER_OFFSET;
+        pub const MAILBOX_START:      usize = START + MAILBOX_OFFSET;
+        pub const GPIO_START:         usize = START + GPIO_OFFSET;
+        pub const PL011_UART_START:   usize = START + UART_OFFSET;
+        pub const GICD_START:         usize =         0xFF84_1000;
+        pub const GICC_START:         usize =         0xFF84_2000;
+        pub const END_INCLUSIVE:      usize =         0xFF84_FFFF;
+    }
+}
+
+/// The end of the physical DRAM the page frame allocator can keep track of: everything below the
+/// MMIO window.
+pub const MAX_DRAM_END_EXCLUSIVE: usize = map::mmio::START;
+
+/// Size of a core's stack, and of the guard below it. Has to match `__rpi_core_stack_size` in
+/// kernel.ld.
+pub const CORE_STACK_SIZE: usize = 512 * 1024;
+
+/// log2 of `CORE_STACK_SIZE`. Within the stacks region, an address is in a guard iff this bit is 0.
+pub const CORE_STACK_SHIFT: usize = {
+    assert!(CORE_STACK_SIZE.is_power_of_two());
+
+    CORE_STACK_SIZE.trailing_zeros() as usize
+};
+
+/// The stacks of all cores, including the guards.
+#[inline(always)]
+pub fn core_stacks_range_inclusive() -> RangeInclusive<usize> {
+    unsafe {
+        RangeInclusive::new(
+            __core_stacks_start.get() as usize,
+            __core_stacks_end_exclusive.get() as usize - 1,
+        )
     }
 }
+
+/// The stack of `core`, without its guard.
+#[inline(always)]
+pub fn core_stack_range_inclusive(core: usize) -> RangeInclusive<usize> {
+    let start = *core_stacks_range_inclusive().start() + (core * 2 + 1) * CORE_STACK_SIZE;
+
+    RangeInclusive::new(start, start + CORE_STACK_SIZE - 1)
+}
+
+/// The guard below the stack of `core`, which is left unmapped.
+#[inline(always)]
+pub fn core_stack_guard_range_inclusive(core: usize) -> RangeInclusive<usize> {
+    let end = *core_stack_range_inclusive(core).start() - 1;
+
+    RangeInclusive::new(end + 1 - CORE_STACK_SIZE, end)
+}
+
+/// Return the core whose stack guard `addr` is in, if any.
+pub fn core_of_stack_guard(addr: usize) -> Option<usize> {
+    (0..super::cpu::NUM_CORES).find(|core| core_stack_guard_range_inclusive(*core).contains(&addr))
+}
+
+/// The kernel's code, `.text`.
+#[inline(always)]
+pub fn code_range_inclusive() -> RangeInclusive<usize> {
+    unsafe {
+        RangeInclusive::new(
+            __code_start.get() as usize,
+            __code_end_exclusive.get() as usize - 1,
+        )
+    }
+}
+
+/// The kernel's read-only data, `.rodata`.
+#[inline(always)]
+pub fn rodata_range_inclusive() -> RangeInclusive<usize> {
+    unsafe {
+        RangeInclusive::new(
+            __rodata_start.get() as usize,
+            __rodata_end_exclusive.get() as usize - 1,
+        )
+    }
+}
+
+/// The kernel's writable data, `.data` and `.bss`.
+#[inline(always)]
+pub fn data_range_inclusive() -> RangeInclusive<usize> {
+    unsafe {
+        RangeInclusive::new(
+            __data_start.get() as usize,
+            __data_end_exclusive.get() as usize - 1,
+        )
+    }
+}
+
+/// The kernel heap.
+#[inline(always)]
+pub fn heap_range_inclusive() -> RangeInclusive<usize> {
+    unsafe {
+        RangeInclusive::new(
+            __heap_start.get() as usize,
+            __heap_end_exclusive.get() as usize - 1,
+        )
+    }
+}
+
+/// Hand the DRAM the firmware left to the ARM to the page frame allocator. The firmware's armstub
+/// and spin table, the kernel image, the heap and the core stacks stay reserved.
+pub fn init_frame_allocator() -> Result<(), &'static str> {
+    let dram = super::drivers::arm_memory()?;
+
+    let reserved = [RangeInclusive::new(
+        map::DRAM_START,
+        virt_to_phys(*core_stacks_range_inclusive().end()),
+    )];
+
+    memory::frame_allocator::frame_allocator().init(dram, &reserved)
+}
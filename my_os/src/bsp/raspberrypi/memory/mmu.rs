@@ -0,0 +1,162 @@
+//! BSP Memory Management Unit.
+
+use super::map as memory_map;
+use crate::memory::{mmu::*, phys_to_virt};
+use core::ops::RangeInclusive;
+
+/// The kernel's address space defined by this BSP.
+pub type KernelAddrSpace = AddressSpace<{ memory_map::END_INCLUSIVE + 1 }>;
+
+/// Start of the kernel's half of the address space. Physical memory is mapped linearly from here
+/// on, and the kernel is linked at this offset, so it has to match `__kernel_virt_start` in
+/// kernel.ld.
+pub const KERNEL_VIRT_START: usize = usize::MAX - KernelAddrSpace::SIZE + 1;
+
+/// The translation granule chosen by this BSP.
+pub type KernelGranule = TranslationGranule<{ 64 * 1024 }>;
+
+/// The physical range the early boot tables map as device memory.
+pub const BOOT_DEVICE_RANGE_INCLUSIVE: RangeInclusive<usize> =
+    RangeInclusive::new(memory_map::mmio::START, memory_map::mmio::END_INCLUSIVE);
+
+/// Where `memory::mmu::kernel_map_mmio()` maps device memory: the virtual addresses past the linear
+/// map of DRAM. Left unmapped until drivers ask for their registers.
+pub const MMIO_REMAP_RANGE_INCLUSIVE: RangeInclusive<usize> = RangeInclusive::new(
+    phys_to_virt(super::MAX_DRAM_END_EXCLUSIVE - 1) + 1,
+    usize::MAX,
+);
+
+const NUM_MEM_RANGES: usize = 11;
+
+// never used for unmapped ranges
+const UNMAPPED_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadOnly,
+    execute_never: true,
+};
+
+/// The virtual memory layout.
+///
+/// The kernel's own sections are mapped with the least permissions that work, nothing is writable
+/// and executable at the same time. The guards below the core stacks are left unmapped, they come
+/// before the stacks so that they take precedence. Device memory is not mapped up front, drivers
+/// map their registers into the MMIO remap window at init. Anything not covered here is mapped
+/// linearly as normal cacheable DRAM, RW and execute-never. That includes the VideoCore's share of
+/// DRAM, which is unmapped again once the mailbox told where it is.
+/// It is agnostic of the paging granularity that the architecture's MMU will use.
+pub static LAYOUT: KernelVirtualLayout<NUM_MEM_RANGES> = KernelVirtualLayout::new(
+    KERNEL_VIRT_START,
+    usize::MAX,
+    [
+        TranslationDescriptor {
+            name: "Kernel code",
+            virtual_range: super::code_range_inclusive,
+            physical_range_translation: Translation::Linear,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadOnly,
+                execute_never: false,
+            },
+        },
+        TranslationDescriptor {
+            name: "Kernel RO data",
+            virtual_range: super::rodata_range_inclusive,
+            physical_range_translation: Translation::Linear,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadOnly,
+                execute_never: true,
+            },
+        },
+        TranslationDescriptor {
+            name: "Kernel data and bss",
+            virtual_range: super::data_range_inclusive,
+            physical_range_translation: Translation::Linear,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        },
+        TranslationDescriptor {
+            name: "Kernel heap",
+            virtual_range: super::heap_range_inclusive,
+            physical_range_translation: Translation::Linear,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        },
+        TranslationDescriptor {
+            name: "Core 0 stack guard",
+            virtual_range: stack_guard_range_inclusive::<0>,
+            physical_range_translation: Translation::Unmapped,
+            attribute_fields: UNMAPPED_ATTRIBUTES,
+        },
+        TranslationDescriptor {
+            name: "Core 1 stack guard",
+            virtual_range: stack_guard_range_inclusive::<1>,
+            physical_range_translation: Translation::Unmapped,
+            attribute_fields: UNMAPPED_ATTRIBUTES,
+        },
+        TranslationDescriptor {
+            name: "Core 2 stack guard",
+            virtual_range: stack_guard_range_inclusive::<2>,
+            physical_range_translation: Translation::Unmapped,
+            attribute_fields: UNMAPPED_ATTRIBUTES,
+        },
+        TranslationDescriptor {
+            name: "Core 3 stack guard",
+            virtual_range: stack_guard_range_inclusive::<3>,
+            physical_range_translation: Translation::Unmapped,
+            attribute_fields: UNMAPPED_ATTRIBUTES,
+        },
+        TranslationDescriptor {
+            name: "Kernel core stacks",
+            virtual_range: super::core_stacks_range_inclusive,
+            physical_range_translation: Translation::Linear,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        },
+        TranslationDescriptor {
+            name: "DRAM",
+            virtual_range: dram_range_inclusive,
+            physical_range_translation: Translation::Linear,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        },
+        TranslationDescriptor {
+            name: "MMIO remap window",
+            virtual_range: mmio_remap_range_inclusive,
+            physical_range_translation: Translation::Unmapped,
+            attribute_fields: UNMAPPED_ATTRIBUTES,
+        },
+    ],
+);
+
+fn stack_guard_range_inclusive<const CORE: usize>() -> RangeInclusive<usize> {
+    super::core_stack_guard_range_inclusive(CORE)
+}
+
+fn dram_range_inclusive() -> RangeInclusive<usize> {
+    RangeInclusive::new(
+        phys_to_virt(memory_map::DRAM_START),
+        phys_to_virt(super::MAX_DRAM_END_EXCLUSIVE - 1),
+    )
+}
+
+fn mmio_remap_range_inclusive() -> RangeInclusive<usize> {
+    MMIO_REMAP_RANGE_INCLUSIVE
+}
+
+/// Return a reference to the virtual memory layout.
+pub fn virt_mem_layout() -> &'static KernelVirtualLayout<NUM_MEM_RANGES> {
+    &LAYOUT
+}
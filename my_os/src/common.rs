@@ -0,0 +1,93 @@
+//! General purpose code.
+
+use core::fmt;
+
+/// A wrapper type for usize with integrated range bound check.
+#[derive(Copy, Clone)]
+pub struct BoundedUsize<const MAX_INCLUSIVE: usize>(usize);
+
+#[allow(dead_code)]
+impl<const MAX_INCLUSIVE: usize> BoundedUsize<{ MAX_INCLUSIVE }> {
+    pub const MAX_INCLUSIVE: usize = MAX_INCLUSIVE;
+
+    /// Creates a new instance if number <= MAX_INCLUSIVE.
+    pub const fn new(number: usize) -> Self {
+        assert!(number <= MAX_INCLUSIVE);
+
+        Self(number)
+    }
+
+    /// Return the wrapped number.
+    pub const fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl<const MAX_INCLUSIVE: usize> fmt::Display for BoundedUsize<{ MAX_INCLUSIVE }> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A fixed size FIFO ring buffer.
+pub struct RingBuffer<T, const N: usize>
+where
+    T: Copy,
+{
+    buf: [T; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> RingBuffer<T, N>
+where
+    T: Copy,
+{
+    /// Create an instance. `init` only fills the backing array, the buffer starts out empty.
+    pub const fn new(init: T) -> Self {
+        Self {
+            buf: [init; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Append an item at the back.
+    pub fn push(&mut self, item: T) -> Result<(), &'static str> {
+        if self.is_full() {
+            return Err("Ring buffer full");
+        }
+
+        self.buf[(self.head + self.len) % N] = item;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Take the oldest item from the front.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let item = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+
+        Some(item)
+    }
+
+    /// Drop everything in the buffer.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
@@ -1,7 +1,14 @@
 //! System Console
+//!
+//! Everything printed goes through the `ConsoleMux`, which passes it on to all registered consoles
+//! and takes input from any of them. The kernel log is one of them from the start.
+
+mod kernel_log;
+mod mux;
+
+pub use kernel_log::KernelLog;
+pub use mux::ConsoleMux;
 
-mod null_console;
-use crate::synchronization::{self, SpinLock};
 // bsp defines the implemention
 pub mod interface {
     use core::fmt;
@@ -22,6 +29,11 @@ pub mod interface {
             ' '
         }
 
+        /// Non-blocking read, `None` if nothing is waiting.
+        fn try_read_char(&self) -> Option<char> {
+            None
+        }
+
         fn clear_rx(&self);
     }
 
@@ -35,22 +47,39 @@ pub mod interface {
         fn chars_read(&self) -> usize {
             0
         }
+
+        /// Chars lost because they came in faster than they were read.
+        fn chars_dropped(&self) -> usize {
+            0
+        }
     }
 
     pub trait All: Write + Read + Stats {}
 }
 
-static CUR_CONSOLE: SpinLock<&'static (dyn interface::All + Sync)> =
-    SpinLock::new(&null_console::NULL_CONSOLE);
-
-use synchronization::interface::Mutex;
+static CONSOLE_MUX: ConsoleMux = ConsoleMux::new();
+static KERNEL_LOG: KernelLog = KernelLog::new();
 
-/// Register a new console.
-pub fn register_console(new_console: &'static (dyn interface::All + Sync)) {
-    CUR_CONSOLE.lock(|con| *con = new_console);
+/// Add a console to the ones the kernel prints to and reads from, under `name`. It starts out
+/// enabled.
+pub fn register_console(
+    name: &'static str,
+    new_console: &'static (dyn interface::All + Sync),
+) -> Result<(), &'static str> {
+    CONSOLE_MUX.add(name, new_console)
 }
 
-/// Return a reference to the currently registered console.
+/// Return a reference to the console, which is all of the registered ones.
 pub fn console() -> &'static dyn interface::All {
-    CUR_CONSOLE.lock(|con| *con)
+    &CONSOLE_MUX
+}
+
+/// Return a reference to the console multiplexer, to enable and disable consoles.
+pub fn console_mux() -> &'static ConsoleMux {
+    &CONSOLE_MUX
+}
+
+/// Return a reference to the kernel log.
+pub fn kernel_log() -> &'static KernelLog {
+    &KERNEL_LOG
 }
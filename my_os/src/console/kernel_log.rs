@@ -0,0 +1,130 @@
+//! Kernel log.
+//!
+//! An in-memory console that keeps the last `CAPACITY` bytes printed, for when nobody was watching
+//! the UART. Anything outside of ASCII is kept as '?'.
+
+use super::interface;
+use crate::{synchronization, synchronization::IRQSafeSpinLock};
+use core::fmt;
+
+/// Bytes kept, older ones get overwritten.
+const CAPACITY: usize = 16 * 1024;
+
+struct KernelLogInner {
+    buffer: [u8; CAPACITY],
+    /// Where the next byte goes.
+    head: usize,
+    /// Bytes kept, up to `CAPACITY`.
+    len: usize,
+    chars_written: usize,
+}
+
+/// A ring buffer of console output.
+pub struct KernelLog {
+    inner: IRQSafeSpinLock<KernelLogInner>,
+}
+
+impl KernelLogInner {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; CAPACITY],
+            head: 0,
+            len: 0,
+            chars_written: 0,
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        self.buffer[self.head] = if c.is_ascii() { c as u8 } else { b'?' };
+        self.head = (self.head + 1) % CAPACITY;
+        self.len = (self.len + 1).min(CAPACITY);
+        self.chars_written += 1;
+    }
+
+    /// Copy the kept bytes from `offset` on, oldest first, into `buf`. Returns how many fit.
+    fn copy_out(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let start = (self.head + CAPACITY - self.len) % CAPACITY;
+        let count = self.len.saturating_sub(offset).min(buf.len());
+
+        for (i, byte) in buf[..count].iter_mut().enumerate() {
+            *byte = self.buffer[(start + offset + i) % CAPACITY];
+        }
+
+        count
+    }
+}
+
+impl fmt::Write for KernelLogInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl KernelLog {
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeSpinLock::new(KernelLogInner::new()),
+        }
+    }
+
+    /// Print the kept log to `console`, oldest first.
+    ///
+    /// The log is copied out in small chunks, so that `console` may well be the one feeding it.
+    /// Only what was kept when the replay started gets printed. Once the log is full, whatever gets
+    /// printed meanwhile shifts the contents though, so the result may be off at the chunk
+    /// boundaries then.
+    pub fn replay(&self, console: &dyn interface::Write) {
+        let mut chunk = [0u8; 64];
+        let mut offset = 0;
+        let len = self.inner.lock(|inner| inner.len);
+
+        while offset < len {
+            let max = chunk.len().min(len - offset);
+            let count = self
+                .inner
+                .lock(|inner| inner.copy_out(offset, &mut chunk[..max]));
+            if count == 0 {
+                break;
+            }
+
+            // only ASCII goes in
+            if let Ok(s) = core::str::from_utf8(&chunk[..count]) {
+                let _ = console.write_fmt(format_args!("{}", s));
+            }
+
+            offset += count;
+        }
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::Mutex;
+
+impl interface::Write for KernelLog {
+    fn write_char(&self, c: char) {
+        self.inner.lock(|inner| inner.write_char(c));
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.inner.lock(|inner| fmt::Write::write_fmt(inner, args))
+    }
+
+    fn flush(&self) {}
+}
+
+impl interface::Read for KernelLog {
+    fn clear_rx(&self) {}
+}
+
+impl interface::Stats for KernelLog {
+    fn chars_written(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_written)
+    }
+}
+
+impl interface::All for KernelLog {}
@@ -0,0 +1,207 @@
+//! Console multiplexer.
+//!
+//! Passes output on to every enabled console and takes input from whichever of them has some.
+//! Consoles are added under a name, which is also how they get enabled and disabled later.
+
+use super::interface;
+use crate::{cpu, exception, info, synchronization, synchronization::RwSpinLock};
+use core::fmt;
+
+/// How many consoles can be added.
+const MAX_BACKENDS: usize = 4;
+
+#[derive(Copy, Clone)]
+struct Backend {
+    name: &'static str,
+    console: &'static (dyn interface::All + Sync),
+    enabled: bool,
+}
+
+type Backends = [Option<Backend>; MAX_BACKENDS];
+
+/// Fans a single console out to several.
+pub struct ConsoleMux {
+    backends: RwSpinLock<Backends>,
+}
+
+/// The enabled consoles, in the order they were added.
+fn enabled(backends: &Backends) -> impl Iterator<Item = &Backend> {
+    backends.iter().flatten().filter(|backend| backend.enabled)
+}
+
+impl ConsoleMux {
+    pub const fn new() -> Self {
+        Self {
+            backends: RwSpinLock::new([None; MAX_BACKENDS]),
+        }
+    }
+
+    /// Add `console` under `name`, enabled.
+    pub fn add(
+        &self,
+        name: &'static str,
+        console: &'static (dyn interface::All + Sync),
+    ) -> Result<(), &'static str> {
+        self.backends.write(|backends| {
+            if backends
+                .iter()
+                .flatten()
+                .any(|backend| backend.name == name)
+            {
+                return Err("Console name already taken");
+            }
+
+            let slot = backends
+                .iter_mut()
+                .find(|backend| backend.is_none())
+                .ok_or("Too many consoles")?;
+
+            *slot = Some(Backend {
+                name,
+                console,
+                enabled: true,
+            });
+
+            Ok(())
+        })
+    }
+
+    /// Start or stop passing output to, and taking input from, the console added as `name`.
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> Result<(), &'static str> {
+        self.backends.write(|backends| {
+            let backend = backends
+                .iter_mut()
+                .flatten()
+                .find(|backend| backend.name == name)
+                .ok_or("No such console")?;
+
+            backend.enabled = enabled;
+
+            Ok(())
+        })
+    }
+
+    /// Print the consoles and what went through each.
+    pub fn print_backends(&self) {
+        // printing comes back through here, and a waiting writer would keep it out
+        let backends = self.backends.read(|backends| *backends);
+
+        for backend in backends.iter().flatten() {
+            info!(
+                "      {:<12} {:<8} {} chars written, {} read",
+                backend.name,
+                if backend.enabled {
+                    "enabled"
+                } else {
+                    "disabled"
+                },
+                backend.console.chars_written(),
+                backend.console.chars_read()
+            );
+        }
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::ReadWriteEx;
+
+impl interface::Write for ConsoleMux {
+    fn write_char(&self, c: char) {
+        self.backends.read(|backends| {
+            for backend in enabled(backends) {
+                backend.console.write_char(c);
+            }
+        })
+    }
+
+    // a console that fails doesn't keep the others from getting the output
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.backends.read(|backends| {
+            enabled(backends).fold(Ok(()), |result, backend| {
+                result.and(backend.console.write_fmt(args))
+            })
+        })
+    }
+
+    fn flush(&self) {
+        self.backends.read(|backends| {
+            for backend in enabled(backends) {
+                backend.console.flush();
+            }
+        })
+    }
+}
+
+impl interface::Read for ConsoleMux {
+    fn read_char(&self) -> char {
+        // IRQs are off, e.g. early boot. nothing will wake us up, so just poll
+        let polling = exception::asynchronous::is_local_irq_masked();
+
+        // As in the UART, check and `wfi` with IRQs masked, so that input arriving in between still
+        // wakes us up. The lock is dropped before sleeping though, a waiting writer would keep
+        // everyone else from printing meanwhile.
+        loop {
+            let c = exception::asynchronous::exec_with_irq_masked(|| {
+                let c = self.backends.read(|backends| {
+                    enabled(backends).find_map(|backend| backend.console.try_read_char())
+                });
+                if c.is_none() && !polling {
+                    cpu::wfi();
+                }
+
+                c
+            });
+
+            match c {
+                Some(c) => return c,
+                None if polling => cpu::nop(),
+                None => (),
+            }
+        }
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        self.backends
+            .read(|backends| enabled(backends).find_map(|backend| backend.console.try_read_char()))
+    }
+
+    fn clear_rx(&self) {
+        self.backends.read(|backends| {
+            for backend in enabled(backends) {
+                backend.console.clear_rx();
+            }
+        })
+    }
+}
+
+// Every enabled console sees the same output, so the one that saw the most has the count. Input
+// comes from all of them.
+impl interface::Stats for ConsoleMux {
+    fn chars_written(&self) -> usize {
+        self.backends.read(|backends| {
+            enabled(backends)
+                .map(|backend| backend.console.chars_written())
+                .max()
+                .unwrap_or(0)
+        })
+    }
+
+    fn chars_read(&self) -> usize {
+        self.backends.read(|backends| {
+            enabled(backends)
+                .map(|backend| backend.console.chars_read())
+                .sum()
+        })
+    }
+
+    fn chars_dropped(&self) -> usize {
+        self.backends.read(|backends| {
+            enabled(backends)
+                .map(|backend| backend.console.chars_dropped())
+                .sum()
+        })
+    }
+}
+
+impl interface::All for ConsoleMux {}
@@ -7,5 +7,10 @@ mod arch_cpu;
 // export boot code
 mod boot;
 
+pub mod smp;
+
 // export the arch spin techinque
-pub use arch_cpu::{nop, wait_forever};
+pub use arch_cpu::{nop, wait_forever, wfi};
+
+#[cfg(feature = "debug_heap")]
+pub use arch_cpu::return_addresses;
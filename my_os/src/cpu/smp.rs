@@ -0,0 +1,126 @@
+//! Symmetric multiprocessing.
+//!
+//! The firmware starts the boot core only and parks the others in its spin table. The boot core
+//! releases them with `start_secondary_cores()`, they then run through the same EL2 to EL1 path as
+//! the boot core and end up in `secondary_core_main()`, waiting for work handed to them with
+//! `run_on_core()`.
+
+#[path = "../_arch/aarch64/cpu/smp.rs"]
+mod arch_smp;
+
+use crate::{bsp, memory, state, timer, warn};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+pub use arch_smp::core_id;
+
+/// Number of cores on the board.
+pub const NUM_CORES: usize = bsp::cpu::NUM_CORES;
+
+/// Function to be run on a core by `run_on_core()`.
+pub type CoreFn = fn();
+
+#[allow(clippy::declare_interior_mutable_const)]
+const OFFLINE: AtomicBool = AtomicBool::new(false);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_WORK: AtomicUsize = AtomicUsize::new(0);
+
+static CORE_ONLINE: [AtomicBool; NUM_CORES] = [OFFLINE; NUM_CORES];
+
+// the `CoreFn` each core should run next, as an address. 0 if there is none.
+static CORE_WORK: [AtomicUsize; NUM_CORES] = [NO_WORK; NUM_CORES];
+
+fn boot_core_id() -> usize {
+    bsp::cpu::BOOT_CORE_ID as usize
+}
+
+/// Return whether `core` is up and running.
+pub fn is_core_online(core: usize) -> bool {
+    core < NUM_CORES && CORE_ONLINE[core].load(Ordering::Acquire)
+}
+
+/// Release the secondary cores from the firmware's spin table and wait for them to come online.
+///
+/// Returns the number of cores online afterwards, boot core included.
+pub fn start_secondary_cores() -> usize {
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    state::state_manager().transition_to_multi_core_main();
+    CORE_ONLINE[boot_core_id()].store(true, Ordering::Release);
+
+    for core in (0..NUM_CORES).filter(|core| *core != boot_core_id()) {
+        let release_addr = memory::phys_to_virt(bsp::cpu::SPIN_TABLE_RELEASE_ADDR[core]);
+        unsafe { arch_smp::release_core(release_addr) };
+
+        if timer::Deadline::after(TIMEOUT)
+            .poll_until(|| is_core_online(core))
+            .is_err()
+        {
+            warn!("Core {} did not come online", core);
+        }
+    }
+
+    (0..NUM_CORES).filter(|core| is_core_online(*core)).count()
+}
+
+/// Run `f` on `core`. Returns right away, use `wait_for_core()` to wait for `f` to finish.
+///
+/// On the executing core itself, `f` simply runs before this returns. Only the boot core can
+/// hand out work.
+pub fn run_on_core(core: usize, f: CoreFn) -> Result<(), &'static str> {
+    if core == core_id::<usize>() {
+        f();
+        return Ok(());
+    }
+
+    if core_id::<usize>() != boot_core_id() {
+        return Err("Only the boot core can hand out work");
+    }
+
+    if !is_core_online(core) {
+        return Err("Core is not online");
+    }
+
+    // only the boot core hands out work, so nobody can sneak in between check and store
+    if CORE_WORK[core].load(Ordering::Acquire) != 0 {
+        return Err("Core is busy");
+    }
+
+    CORE_WORK[core].store(f as usize, Ordering::Release);
+    arch_smp::send_event();
+
+    Ok(())
+}
+
+/// Wait until `core` finished the function handed to it by `run_on_core()`.
+pub fn wait_for_core(core: usize) {
+    while CORE_WORK[core].load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Where the secondary cores end up after boot. Marks the core online and runs whatever is handed
+/// to it with `run_on_core()`.
+pub fn secondary_core_main() -> ! {
+    let core: usize = core_id();
+
+    CORE_ONLINE[core].store(true, Ordering::Release);
+
+    loop {
+        let work = CORE_WORK[core].load(Ordering::Acquire);
+
+        if work == 0 {
+            // a `run_on_core()` right before this still wakes us up, its event is pending
+            arch_smp::wait_for_event();
+            continue;
+        }
+
+        let f: CoreFn = unsafe { core::mem::transmute(work) };
+        f();
+
+        CORE_WORK[core].store(0, Ordering::Release);
+    }
+}
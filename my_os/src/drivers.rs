@@ -1,19 +1,19 @@
 //! Driver support.
 
 use crate::{
-    println,
-    synchronization::{interface::Mutex, SpinLock},
+    exception, println,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
 };
-
-const NUM_DRIVERS: usize = 1 << 8;
+use alloc::vec::Vec;
 
 struct DriverManagerInner {
-    next_index: usize,
-    descriptors: [Option<DeviceDriverDescriptor>; NUM_DRIVERS],
+    descriptors: Vec<DeviceDriverDescriptor>,
 }
 
 // driver interfaces
 pub mod interface {
+    use crate::exception::asynchronous::IRQNumber;
+
     pub trait DeviceDriver {
         /// Return a compatibility string for identifying the driver.
         fn compatible(&self) -> &'static str;
@@ -22,6 +22,21 @@ pub mod interface {
         unsafe fn init(&self) -> Result<(), &'static str> {
             Ok(())
         }
+
+        /// Called by the kernel to register and enable the device's IRQ handler.
+        ///
+        /// Rust's type system will prevent a call to this function unless the calling instance
+        /// itself has static lifetime.
+        fn register_and_enable_irq_handler(
+            &'static self,
+            irq_number: &IRQNumber,
+        ) -> Result<(), &'static str> {
+            panic!(
+                "Attempt to enable IRQ {} for device {}, but driver does not support this",
+                irq_number,
+                self.compatible()
+            )
+        }
     }
 }
 
@@ -33,11 +48,12 @@ pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), &'static str>;
 pub struct DeviceDriverDescriptor {
     device_driver: &'static (dyn interface::DeviceDriver + Sync),
     post_init_callback: Option<DeviceDriverPostInitCallback>,
+    irq_number: Option<exception::asynchronous::IRQNumber>,
 }
 
 /// Provides device driver management functions.
 pub struct DriverManager {
-    inner: SpinLock<DriverManagerInner>,
+    inner: InitStateLock<DriverManagerInner>,
 }
 
 /// Global
@@ -46,8 +62,7 @@ static DRIVER_MANAGER: DriverManager = DriverManager::new();
 impl DriverManagerInner {
     pub const fn new() -> Self {
         Self {
-            next_index: 0,
-            descriptors: [None; NUM_DRIVERS],
+            descriptors: Vec::new(),
         }
     }
 }
@@ -56,10 +71,12 @@ impl DeviceDriverDescriptor {
     pub fn new(
         device_driver: &'static (dyn interface::DeviceDriver + Sync),
         post_init_callback: Option<DeviceDriverPostInitCallback>,
+        irq_number: Option<exception::asynchronous::IRQNumber>,
     ) -> Self {
         Self {
             device_driver,
             post_init_callback,
+            irq_number,
         }
     }
 }
@@ -72,31 +89,23 @@ pub fn driver_manager() -> &'static DriverManager {
 impl DriverManager {
     pub const fn new() -> Self {
         Self {
-            inner: SpinLock::new(DriverManagerInner::new()),
+            inner: InitStateLock::new(DriverManagerInner::new()),
         }
     }
 
     // register a device driver
     pub fn register_driver(&self, descriptor: DeviceDriverDescriptor) {
-        self.inner.lock(|inner| {
-            inner.descriptors[inner.next_index] = Some(descriptor);
-            inner.next_index += 1;
-        })
+        self.inner.write(|inner| inner.descriptors.push(descriptor))
     }
 
     // helper for iterating over registered drivers.
     fn for_each_descriptor<'a>(&'a self, f: impl FnMut(&'a DeviceDriverDescriptor)) {
-        self.inner.lock(|inner| {
-            inner
-                .descriptors
-                .iter()
-                .filter_map(|x| x.as_ref())
-                .for_each(f)
-        })
+        self.inner
+            .read(|inner| inner.descriptors.iter().for_each(f))
     }
 
-    // fully initialize all drivers.
-    pub unsafe fn init_drivers(&self) {
+    // fully initialize all drivers and their interrupts handlers.
+    pub unsafe fn init_drivers_and_irqs(&self) {
         self.for_each_descriptor(|descriptor| {
             // 1. Initialize driver.
             if let Err(x) = descriptor.device_driver.init() {
@@ -118,6 +127,23 @@ impl DriverManager {
                 }
             }
         });
+
+        // 3. After all post-init callbacks were done, the interrupt controller should be
+        //    registered and functional. So let drivers register with it now.
+        self.for_each_descriptor(|descriptor| {
+            if let Some(irq_number) = &descriptor.irq_number {
+                if let Err(x) = descriptor
+                    .device_driver
+                    .register_and_enable_irq_handler(irq_number)
+                {
+                    panic!(
+                        "Error during driver interrupt handler registration: {}: {}",
+                        descriptor.device_driver.compatible(),
+                        x
+                    );
+                }
+            }
+        });
     }
 
     /// Enumerate all registered device drivers.
@@ -0,0 +1,62 @@
+//! Framebuffer
+//!
+//! Drawing on a screen, whatever driver is behind it. Coordinates are in pixels, with the origin in
+//! the top left corner. The `FramebufferConsole` prints text on top of that.
+
+mod console;
+mod font;
+
+pub use console::FramebufferConsole;
+
+/// A 24 bit color.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Self = Self::new(0x00, 0x00, 0x00);
+    pub const LIGHT_GRAY: Self = Self::new(0xAA, 0xAA, 0xAA);
+    pub const WHITE: Self = Self::new(0xFF, 0xFF, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// Framebuffer interfaces.
+pub mod interface {
+    use super::Color;
+
+    /// Drawing primitives. Whatever falls outside of the screen is left out.
+    pub trait Framebuffer {
+        /// Width in pixels, 0 if there is no screen.
+        fn width(&self) -> usize;
+
+        /// Height in pixels, 0 if there is no screen.
+        fn height(&self) -> usize;
+
+        fn put_pixel(&self, x: usize, y: usize, color: Color);
+
+        /// Draw `pixels` side by side, the first one at `x`, `y`.
+        fn put_pixels(&self, x: usize, y: usize, pixels: &[Color]) {
+            for (i, color) in pixels.iter().enumerate() {
+                self.put_pixel(x + i, y, *color);
+            }
+        }
+
+        fn fill_rect(&self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+            for y in y..y.saturating_add(height).min(self.height()) {
+                for x in x..x.saturating_add(width).min(self.width()) {
+                    self.put_pixel(x, y, color);
+                }
+            }
+        }
+
+        /// Move the picture up by `rows` pixels. The rows that come free at the bottom get filled
+        /// with `color`.
+        fn scroll_up(&self, rows: usize, color: Color);
+    }
+}
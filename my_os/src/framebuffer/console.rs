@@ -0,0 +1,258 @@
+//! Framebuffer console.
+//!
+//! Text in the built-in font on a grid of character cells, scrolling up once the last line is
+//! full. Understands the few ANSI escape sequences the kernel prints:
+//!
+//! - `ESC[2J` clears the screen, `ESC[J` and `ESC[0J` from the cursor on.
+//! - `ESC[<row>;<col>H` moves the cursor, 1-based, `ESC[H` to the top left.
+//! - `ESC[1m` turns on bold, `ESC[0m` or `ESC[m` back off.
+//!
+//! Other sequences are swallowed. There is no keyboard, so the console has no input.
+
+use super::{font, interface, Color};
+use crate::{console, synchronization, synchronization::IRQSafeSpinLock};
+use core::fmt;
+
+const BACKGROUND: Color = Color::BLACK;
+const FOREGROUND: Color = Color::LIGHT_GRAY;
+const FOREGROUND_BOLD: Color = Color::WHITE;
+
+const TAB_WIDTH: usize = 8;
+
+/// Parameters an escape sequence can have, more are ignored.
+const MAX_PARAMS: usize = 2;
+
+const ESC: char = '\x1B';
+
+/// Where in an escape sequence the last char left off.
+#[derive(Copy, Clone)]
+enum EscapeState {
+    Normal,
+    /// Got `ESC`.
+    Escape,
+    /// Got `ESC[`, and parameters up to `params[index]`.
+    Csi {
+        params: [usize; MAX_PARAMS],
+        index: usize,
+    },
+}
+
+struct FramebufferConsoleInner {
+    framebuffer: &'static (dyn interface::Framebuffer + Sync),
+    column: usize,
+    row: usize,
+    bold: bool,
+    escape: EscapeState,
+    chars_written: usize,
+}
+
+/// A text console on a framebuffer.
+pub struct FramebufferConsole {
+    inner: IRQSafeSpinLock<FramebufferConsoleInner>,
+}
+
+impl FramebufferConsoleInner {
+    const fn new(framebuffer: &'static (dyn interface::Framebuffer + Sync)) -> Self {
+        Self {
+            framebuffer,
+            column: 0,
+            row: 0,
+            bold: false,
+            escape: EscapeState::Normal,
+            chars_written: 0,
+        }
+    }
+
+    fn columns(&self) -> usize {
+        self.framebuffer.width() / font::WIDTH
+    }
+
+    fn rows(&self) -> usize {
+        self.framebuffer.height() / font::HEIGHT
+    }
+
+    fn write_char(&mut self, c: char) {
+        // no screen, nothing to do
+        if self.columns() == 0 || self.rows() == 0 {
+            return;
+        }
+
+        self.escape = match self.escape {
+            EscapeState::Normal => {
+                self.print_char(c);
+                self.chars_written += 1;
+
+                return;
+            }
+            EscapeState::Escape if c == '[' => EscapeState::Csi {
+                params: [0; MAX_PARAMS],
+                index: 0,
+            },
+            // not a sequence we know, drop it
+            EscapeState::Escape => EscapeState::Normal,
+            EscapeState::Csi { mut params, index } => match c {
+                '0'..='9' => {
+                    let digit = c as usize - '0' as usize;
+                    params[index] = params[index].saturating_mul(10).saturating_add(digit);
+
+                    EscapeState::Csi { params, index }
+                }
+                ';' => EscapeState::Csi {
+                    params,
+                    index: (index + 1).min(MAX_PARAMS - 1),
+                },
+                _ => {
+                    self.execute_csi(c, &params);
+
+                    EscapeState::Normal
+                }
+            },
+        };
+    }
+
+    fn print_char(&mut self, c: char) {
+        match c {
+            ESC => self.escape = EscapeState::Escape,
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\t' => {
+                for _ in 0..TAB_WIDTH - self.column % TAB_WIDTH {
+                    self.print_char(' ');
+                }
+            }
+            '\x08' => self.column = self.column.saturating_sub(1),
+            c => {
+                // wrap only once there is something to put on the next line
+                if self.column >= self.columns() {
+                    self.new_line();
+                }
+
+                self.draw_glyph(c);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Carriage return and line feed, scrolling if the cursor is on the last line.
+    fn new_line(&mut self) {
+        self.column = 0;
+
+        if self.row + 1 < self.rows() {
+            self.row += 1;
+        } else {
+            self.framebuffer.scroll_up(font::HEIGHT, BACKGROUND);
+        }
+    }
+
+    fn draw_glyph(&self, c: char) {
+        let glyph = font::glyph(c);
+        let foreground = if self.bold {
+            FOREGROUND_BOLD
+        } else {
+            FOREGROUND
+        };
+        let x0 = self.column * font::WIDTH;
+        let y0 = self.row * font::HEIGHT;
+
+        for (y, bits) in glyph.iter().enumerate() {
+            // bold smears every pixel one to the right
+            let bits = if self.bold { bits | (bits >> 1) } else { *bits };
+
+            let mut row = [BACKGROUND; font::WIDTH];
+            for (x, color) in row.iter_mut().enumerate() {
+                if bits & (0x80 >> x) != 0 {
+                    *color = foreground;
+                }
+            }
+
+            self.framebuffer.put_pixels(x0, y0 + y, &row);
+        }
+    }
+
+    fn execute_csi(&mut self, command: char, params: &[usize; MAX_PARAMS]) {
+        match command {
+            // erase in display
+            'J' => match params[0] {
+                0 => {
+                    let width = self.framebuffer.width();
+                    let height = self.framebuffer.height();
+                    let x = self.column * font::WIDTH;
+                    let y = self.row * font::HEIGHT;
+
+                    self.framebuffer
+                        .fill_rect(x, y, width, font::HEIGHT, BACKGROUND);
+                    self.framebuffer
+                        .fill_rect(0, y + font::HEIGHT, width, height, BACKGROUND);
+                }
+                2 => {
+                    let width = self.framebuffer.width();
+                    let height = self.framebuffer.height();
+
+                    self.framebuffer.fill_rect(0, 0, width, height, BACKGROUND);
+                }
+                _ => (),
+            },
+            // cursor position, 1-based, 0 counts as 1
+            'H' => {
+                self.row = params[0].saturating_sub(1).min(self.rows() - 1);
+                self.column = params[1].saturating_sub(1).min(self.columns() - 1);
+            }
+            // select graphic rendition, only the first parameter counts
+            'm' => match params[0] {
+                0 => self.bold = false,
+                1 => self.bold = true,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Write for FramebufferConsoleInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl FramebufferConsole {
+    /// Create an instance that draws on `framebuffer`.
+    pub const fn new(framebuffer: &'static (dyn interface::Framebuffer + Sync)) -> Self {
+        Self {
+            inner: IRQSafeSpinLock::new(FramebufferConsoleInner::new(framebuffer)),
+        }
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::Mutex;
+
+impl console::interface::Write for FramebufferConsole {
+    fn write_char(&self, c: char) {
+        self.inner.lock(|inner| inner.write_char(c));
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.inner.lock(|inner| fmt::Write::write_fmt(inner, args))
+    }
+
+    // drawing goes straight to the framebuffer
+    fn flush(&self) {}
+}
+
+// nothing is ever going to come
+impl console::interface::Read for FramebufferConsole {
+    fn clear_rx(&self) {}
+}
+
+impl console::interface::Stats for FramebufferConsole {
+    fn chars_written(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_written)
+    }
+}
+
+impl console::interface::All for FramebufferConsole {}
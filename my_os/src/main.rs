@@ -5,6 +5,7 @@
 //!     - It is implemented in `src/_arch/aarch64/cpu/boot.s`.
 
 #![allow(clippy::upper_case_acronyms)]
+#![feature(alloc_error_handler)]
 #![feature(asm_const)]
 #![feature(const_option)]
 #![feature(format_args_nl)]
@@ -15,13 +16,19 @@
 #![no_main]
 #![no_std]
 
+extern crate alloc;
+
 mod bsp;
+mod common;
 mod console;
 mod cpu;
 mod drivers;
 mod exception;
+mod framebuffer;
+mod memory;
 mod panic_wait;
 mod print;
+mod state;
 mod synchronization;
 mod timer;
 
@@ -37,31 +44,131 @@ const BOOT_SCREEN: &str = r#"
 /_/ |_|\____//____//_/     \____//____/  
 "#;
 
-// boot.s calls this
+// boot.s sends the boot core here, in EL1 and in the higher half, with the MMU on
+#[no_mangle]
 unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    // install the exception vector table
+    exception::handling_init();
+
+    // the MMU and the caches are on, which the atomics the locks are built on need
+    synchronization::enable_exclusives();
+
+    // keep everything printed from here on, whatever consoles come up later
+    if let Err(x) = console::register_console("log", console::kernel_log()) {
+        panic!("Error registering the kernel log: {}", x);
+    }
+
+    // swap the coarse boot mapping for the kernel's own tables
+    if let Err(string) = memory::mmu::mmu().install_kernel_tables() {
+        panic!("MMU: {}", string);
+    }
+
+    // panics went to the UART through the boot tables so far, which are gone now
+    if let Err(x) = bsp::drivers::map_panic_console() {
+        panic!("Error mapping the panic console: {}", x);
+    }
+
+    // everything from here on may allocate
+    if let Err(x) = memory::heap_alloc::kernel_init_heap_allocator() {
+        panic!("Error initializing kernel heap: {}", x);
+    }
+
     // init the driver subsystem.
     if let Err(x) = bsp::drivers::init() {
         panic!("Error initializing BSP driver subsystem: {}", x);
     }
 
-    // init all the drivers
-    drivers::driver_manager().init_drivers();
+    // init all the drivers and their irq handlers
+    drivers::driver_manager().init_drivers_and_irqs();
+
+    // the firmware tells the mailbox how much DRAM there is, the rest goes to the frame allocator
+    if let Err(x) = bsp::memory::init_frame_allocator() {
+        panic!("Error initializing page frame allocator: {}", x);
+    }
+    if let Err(x) = memory::frame_allocator::frame_allocator().sanity_check() {
+        panic!("Page frame allocator broken: {}", x);
+    }
+
+    // init is done, everything registered so far is read-only from here on
+    state::state_manager().transition_to_single_core_main();
+
+    // handlers are in place, let the IRQs in
+    exception::asynchronous::local_irq_unmask();
 
     kernel_main()
 }
 
+// boot.s sends the secondary cores here once the boot core released them
+#[no_mangle]
+unsafe fn kernel_init_secondary() -> ! {
+    use memory::mmu::interface::MMU;
+
+    // every core has its own vector base register and MMU
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().install_kernel_tables() {
+        panic!("MMU on core {}: {}", cpu::smp::core_id::<usize>(), string);
+    }
+
+    cpu::smp::secondary_core_main()
+}
+
+// run on every core once
+fn smp_ping() {
+    info!("Hello from core {}", cpu::smp::core_id::<usize>());
+}
+
 fn kernel_main() -> ! {
+    use alloc::boxed::Box;
     use console::console;
     use core::time::Duration;
 
     print_boot_screen();
 
+    #[cfg(feature = "debug_heap")]
+    let heap_checkpoint = memory::heap_alloc::leak_checkpoint();
+
+    // bring up the other cores and make each of them run something
+    let cores_online = cpu::smp::start_secondary_cores();
+    info!("Cores online: {}", cores_online);
+
+    for core in 0..cpu::smp::NUM_CORES {
+        if cpu::smp::run_on_core(core, smp_ping).is_ok() {
+            cpu::smp::wait_for_core(core);
+        }
+    }
+
+    // the timer IRQ interrupts the spinning below
+    timer::time_manager().set_timeout_once(
+        Duration::from_millis(1500),
+        Box::new(|| info!("Timeout after 1.5 seconds")),
+    );
+    timer::time_manager().set_timeout_once(
+        Duration::from_millis(3500),
+        Box::new(|| info!("Timeout after 3.5 seconds")),
+    );
+
+    let spin_start = timer::Instant::now();
     for run in 1..=5 {
         info!("Run {} - Spinning for 1 second", run);
         timer::time_manager().spin_for(Duration::from_secs(1));
     }
+    info!("Spun for {:?} in total", spin_start.elapsed());
 
-    // echo mode.
+    // nothing above should have left anything on the heap
+    #[cfg(feature = "debug_heap")]
+    {
+        info!("Heap allocations left over since boot:");
+        memory::heap_alloc::print_leaks(heap_checkpoint);
+    }
+
+    // echo mode. keystrokes don't belong in the kernel log
+    info!("Echoing input now");
+    if let Err(x) = console::console_mux().set_enabled("log", false) {
+        warn!("Could not stop logging: {}", x);
+    }
     console().clear_rx();
     loop {
         let c = console().read_char();
@@ -79,20 +186,78 @@ fn print_boot_screen() {
 
     info!("Booting on: {}", bsp::board_name());
 
+    match bsp::board::board_info() {
+        Some(board_info) => {
+            info!("      {}", board_info);
+
+            if !board_info.matches_bsp() {
+                warn!(
+                    "Kernel was built for a different SoC than {}",
+                    board_info.soc
+                );
+            }
+        }
+        None => warn!("Board unknown, assuming {}", bsp::board_name()),
+    }
+
+    for (name, range) in [
+        ("ARM", bsp::drivers::arm_memory()),
+        ("VideoCore", bsp::drivers::vc_memory()),
+    ] {
+        match range {
+            Ok(range) => info!(
+                "      {} memory: {:#010x} - {:#010x} | {} MiB",
+                name,
+                range.start(),
+                range.end(),
+                (range.end() - range.start() + 1) >> 20
+            ),
+            Err(x) => warn!("{} memory unknown: {}", name, x),
+        }
+    }
+
+    info!("MMU online. Kernel mapping:");
+    bsp::memory::mmu::virt_mem_layout().print_layout();
+
+    info!("MMIO mappings:");
+    memory::mmu::print_mmio_mappings();
+
+    info!("Physical memory:");
+    memory::frame_allocator::frame_allocator().print_stats();
+
+    info!("Kernel heap:");
+    memory::heap_alloc::print_heap_stats();
+
     let (_, priv_level) = exception::current_privilege_level();
     info!("Current Privilge Level: {}", priv_level);
 
     info!("Exception handling state:");
     exception::asynchronous::print_state();
 
+    info!("Consoles:");
+    console::console_mux().print_backends();
+
+    info!("Console UART: {}", bsp::drivers::uart_config());
+
+    match bsp::drivers::framebuffer_info() {
+        Some(info) => info!("Framebuffer: {}", info),
+        None => info!("Framebuffer: none"),
+    }
+
     info!(
         "Architectural timer resolution: {} ns",
         timer::time_manager().resolution().as_nanos()
     );
 
+    info!("Clock sources:");
+    timer::time_manager().cross_check_clock_sources();
+
     println!("Drivers loaded:");
     drivers::driver_manager().enumerate();
 
+    info!("Registered IRQ handlers:");
+    exception::asynchronous::irq_manager().print_handler();
+
     print!("{}", RESET_TEXT);
 
     // Test a failing timer case.
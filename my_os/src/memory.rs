@@ -0,0 +1,40 @@
+//! Memory Management.
+
+#[path = "_arch/aarch64/memory.rs"]
+mod arch_memory;
+
+pub mod frame_allocator;
+pub mod heap_alloc;
+pub mod mmu;
+
+use crate::bsp;
+
+pub use arch_memory::{clean_dcache_range, invalidate_dcache_range};
+
+/// The kernel virtual address of a physical DRAM address.
+///
+/// DRAM is mapped linearly at the start of the kernel's half of the address space. Device MMIO is
+/// not, the MMIO remap window starts right behind DRAM. Drivers get their registers from
+/// `mmu::kernel_map_mmio()` instead.
+#[inline(always)]
+pub const fn phys_to_virt(phys_addr: usize) -> usize {
+    assert!(
+        phys_addr < bsp::memory::MAX_DRAM_END_EXCLUSIVE,
+        "phys_to_virt() of an address past DRAM"
+    );
+
+    phys_addr + bsp::memory::mmu::KERNEL_VIRT_START
+}
+
+/// Where the early boot tables put the device memory at `phys_addr`: linearly, like DRAM. Only
+/// good until the kernel's own tables are installed.
+#[inline(always)]
+pub const fn boot_mmio_phys_to_virt(phys_addr: usize) -> usize {
+    phys_addr + bsp::memory::mmu::KERNEL_VIRT_START
+}
+
+/// The physical address behind a kernel virtual address from the linear mapping.
+#[inline(always)]
+pub const fn virt_to_phys(virt_addr: usize) -> usize {
+    virt_addr - bsp::memory::mmu::KERNEL_VIRT_START
+}
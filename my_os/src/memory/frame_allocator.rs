@@ -0,0 +1,322 @@
+//! Physical page frame allocator.
+//!
+//! Tracks physical DRAM in 4 KiB frames, one bit per frame. A 64 KiB frame is 16 free 4 KiB frames
+//! starting at a 64 KiB aligned address.
+
+use crate::{bsp, info, synchronization, synchronization::IRQSafeSpinLock};
+use core::ops::RangeInclusive;
+
+/// Size of the frames the allocator keeps track of.
+pub const FRAME_SIZE: usize = 4 * 1024;
+
+const MAX_FRAMES: usize = bsp::memory::MAX_DRAM_END_EXCLUSIVE / FRAME_SIZE;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+const BITMAP_WORDS: usize = (MAX_FRAMES + BITS_PER_WORD - 1) / BITS_PER_WORD;
+
+/// How many reserved ranges `init()` takes.
+const MAX_RESERVED: usize = 4;
+
+/// Frame sizes the allocator hands out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameSize {
+    /// 4 KiB.
+    Size4KiB,
+    /// 64 KiB, the kernel's page size.
+    Size64KiB,
+}
+
+/// Frame counts, in 4 KiB frames.
+#[derive(Copy, Clone)]
+struct FrameStats {
+    total: usize,
+    reserved: usize,
+    allocated: usize,
+}
+
+struct FrameAllocatorInner {
+    /// One bit per frame, set if the frame can't be handed out.
+    bitmap: [u64; BITMAP_WORDS],
+
+    /// Where the search for a free 4 KiB frame starts.
+    next_word: usize,
+
+    usable: RangeInclusive<usize>,
+
+    /// The reserved ranges, as first and last frame. The bitmap has them as used just like
+    /// allocated frames, so `free()` checks against these.
+    reserved: [(usize, usize); MAX_RESERVED],
+    num_reserved: usize,
+
+    stats: FrameStats,
+    initialized: bool,
+}
+
+/// The physical page frame allocator.
+pub struct FrameAllocator {
+    inner: IRQSafeSpinLock<FrameAllocatorInner>,
+}
+
+static FRAME_ALLOCATOR: FrameAllocator = FrameAllocator::new();
+
+/// Return a reference to the global FrameAllocator.
+pub fn frame_allocator() -> &'static FrameAllocator {
+    &FRAME_ALLOCATOR
+}
+
+impl FrameSize {
+    /// The size in bytes.
+    pub const fn bytes(self) -> usize {
+        match self {
+            FrameSize::Size4KiB => 4 * 1024,
+            FrameSize::Size64KiB => 64 * 1024,
+        }
+    }
+
+    const fn num_frames(self) -> usize {
+        self.bytes() / FRAME_SIZE
+    }
+}
+
+impl FrameStats {
+    const fn new() -> Self {
+        Self {
+            total: 0,
+            reserved: 0,
+            allocated: 0,
+        }
+    }
+
+    fn free(&self) -> usize {
+        self.total - self.reserved - self.allocated
+    }
+}
+
+impl FrameAllocatorInner {
+    const fn new() -> Self {
+        Self {
+            bitmap: [0; BITMAP_WORDS],
+            next_word: 0,
+            usable: RangeInclusive::new(0, 0),
+            reserved: [(0, 0); MAX_RESERVED],
+            num_reserved: 0,
+            stats: FrameStats::new(),
+            initialized: false,
+        }
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / BITS_PER_WORD] & (1 << (frame % BITS_PER_WORD)) != 0
+    }
+
+    /// Mark `count` frames starting at `first`. Returns how many of them changed state.
+    fn mark(&mut self, first: usize, count: usize, used: bool) -> usize {
+        let mut changed = 0;
+
+        for frame in first..first + count {
+            if self.is_used(frame) != used {
+                self.bitmap[frame / BITS_PER_WORD] ^= 1 << (frame % BITS_PER_WORD);
+                changed += 1;
+            }
+        }
+
+        changed
+    }
+
+    fn init(
+        &mut self,
+        usable: RangeInclusive<usize>,
+        reserved: &[RangeInclusive<usize>],
+    ) -> Result<(), &'static str> {
+        if self.initialized {
+            return Err("Frame allocator already initialized");
+        }
+        if reserved.len() > MAX_RESERVED {
+            return Err("Too many reserved ranges");
+        }
+
+        // only whole frames, and only as much as the bitmap covers
+        let start = (*usable.start() + FRAME_SIZE - 1) / FRAME_SIZE;
+        let end_exclusive = usable
+            .end()
+            .saturating_add(1)
+            .min(bsp::memory::MAX_DRAM_END_EXCLUSIVE)
+            / FRAME_SIZE;
+        if start >= end_exclusive {
+            return Err("No usable DRAM");
+        }
+
+        self.bitmap.fill(u64::MAX);
+        self.stats.total = self.mark(start, end_exclusive - start, false);
+        self.usable = RangeInclusive::new(start * FRAME_SIZE, end_exclusive * FRAME_SIZE - 1);
+
+        // partly covered frames are reserved as a whole
+        for range in reserved {
+            let first = (*range.start() / FRAME_SIZE).max(start);
+            let last = (*range.end() / FRAME_SIZE).min(end_exclusive - 1);
+
+            if first <= last {
+                self.stats.reserved += self.mark(first, last - first + 1, true);
+                self.reserved[self.num_reserved] = (first, last);
+                self.num_reserved += 1;
+            }
+        }
+
+        self.initialized = true;
+
+        Ok(())
+    }
+
+    fn alloc_4k(&mut self) -> Option<usize> {
+        let words = (self.next_word..BITMAP_WORDS).chain(0..self.next_word);
+
+        for word in words {
+            let free = !self.bitmap[word];
+            if free == 0 {
+                continue;
+            }
+
+            let bit = free.trailing_zeros() as usize;
+            self.bitmap[word] |= 1 << bit;
+            self.next_word = word;
+
+            return Some(word * BITS_PER_WORD + bit);
+        }
+
+        None
+    }
+
+    fn alloc_64k(&mut self) -> Option<usize> {
+        const FRAMES: usize = FrameSize::Size64KiB.num_frames();
+        const MASK: u64 = (1 << FRAMES) - 1;
+
+        for word in 0..BITMAP_WORDS {
+            for shift in (0..BITS_PER_WORD).step_by(FRAMES) {
+                if self.bitmap[word] & (MASK << shift) == 0 {
+                    self.bitmap[word] |= MASK << shift;
+
+                    return Some(word * BITS_PER_WORD + shift);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn alloc(&mut self, size: FrameSize) -> Option<usize> {
+        let frame = match size {
+            FrameSize::Size4KiB => self.alloc_4k(),
+            FrameSize::Size64KiB => self.alloc_64k(),
+        }?;
+        self.stats.allocated += size.num_frames();
+
+        Some(frame * FRAME_SIZE)
+    }
+
+    fn free(&mut self, phys_addr: usize, size: FrameSize) -> Result<(), &'static str> {
+        if phys_addr % size.bytes() != 0 {
+            return Err("Frame address not aligned");
+        }
+        if !self.usable.contains(&phys_addr)
+            || !self.usable.contains(&(phys_addr + size.bytes() - 1))
+        {
+            return Err("Frame outside of usable DRAM");
+        }
+
+        let first = phys_addr / FRAME_SIZE;
+        let count = size.num_frames();
+        let last = first + count - 1;
+        if self.reserved[..self.num_reserved]
+            .iter()
+            .any(|(reserved_first, reserved_last)| {
+                first <= *reserved_last && *reserved_first <= last
+            })
+        {
+            return Err("Frame is reserved");
+        }
+        if (first..first + count).any(|frame| !self.is_used(frame)) {
+            return Err("Frame is not allocated");
+        }
+
+        self.mark(first, count, false);
+        self.stats.allocated -= count;
+
+        Ok(())
+    }
+}
+
+impl FrameAllocator {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeSpinLock::new(FrameAllocatorInner::new()),
+        }
+    }
+
+    /// Take over the frames in `usable`, except for the ones overlapping any of the `reserved`
+    /// ranges. Physical addresses.
+    pub fn init(
+        &self,
+        usable: RangeInclusive<usize>,
+        reserved: &[RangeInclusive<usize>],
+    ) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init(usable, reserved))
+    }
+
+    /// Allocate a frame. Returns its physical address, aligned to its size.
+    pub fn alloc(&self, size: FrameSize) -> Option<usize> {
+        self.inner.lock(|inner| inner.alloc(size))
+    }
+
+    /// Give back a frame from `alloc()`, with the same size it was allocated with.
+    pub fn free(&self, phys_addr: usize, size: FrameSize) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.free(phys_addr, size))
+    }
+
+    /// Allocate and free a frame of every size, to catch a broken bitmap before anyone relies on
+    /// it. The counts have to come out as they were before.
+    pub fn sanity_check(&self) -> Result<(), &'static str> {
+        for size in [FrameSize::Size4KiB, FrameSize::Size64KiB] {
+            let (usable, allocated) = self
+                .inner
+                .lock(|inner| (inner.usable.clone(), inner.stats.allocated));
+
+            let phys_addr = self.alloc(size).ok_or("Out of frames")?;
+            if phys_addr % size.bytes() != 0 || !usable.contains(&phys_addr) {
+                return Err("Allocated a frame that isn't aligned or usable");
+            }
+
+            self.free(phys_addr, size)?;
+            if self.free(phys_addr, size).is_ok() {
+                return Err("Freed a frame twice");
+            }
+
+            if self.inner.lock(|inner| inner.stats.allocated) != allocated {
+                return Err("Frame counts off after alloc and free");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print the usable DRAM and the frame counts.
+    pub fn print_stats(&self) {
+        let (usable, stats) = self.inner.lock(|inner| (inner.usable.clone(), inner.stats));
+
+        info!(
+            "      Usable DRAM: {:#010x} - {:#010x}",
+            usable.start(),
+            usable.end()
+        );
+        info!("      Frames ({} KiB):", FRAME_SIZE / 1024);
+        info!("          {: >8} total", stats.total);
+        info!("          {: >8} reserved", stats.reserved);
+        info!("          {: >8} allocated", stats.allocated);
+        info!(
+            "          {: >8} free ({} MiB)",
+            stats.free(),
+            (stats.free() * FRAME_SIZE) >> 20
+        );
+    }
+}
+
+use synchronization::interface::Mutex;
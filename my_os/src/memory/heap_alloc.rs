@@ -0,0 +1,336 @@
+//! Kernel heap.
+//!
+//! A first-fit free list over the heap region the `BSP` reserves. Free blocks are kept sorted by
+//! address and merged with their neighbours when memory comes back, the list lives in the free
+//! memory itself.
+//!
+//! With the `debug_heap` feature, allocations get tracked and guarded, see `debug`.
+
+#[cfg(feature = "debug_heap")]
+mod debug;
+
+use crate::{bsp, info, synchronization, synchronization::IRQSafeSpinLock};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{fmt, mem, ptr};
+
+/// Every block is aligned to and a multiple of this, so that a free block header fits anywhere.
+const BLOCK_ALIGN: usize = 16;
+const MIN_BLOCK_SIZE: usize = mem::size_of::<FreeBlock>();
+
+/// Header of a free block, at the block's start.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Heap usage counters.
+#[derive(Copy, Clone)]
+pub struct HeapStats {
+    /// Size of the heap region.
+    pub size: usize,
+    /// Bytes handed out, including alignment padding.
+    pub used: usize,
+    /// The largest `used` has ever been.
+    pub peak: usize,
+    /// Number of successful allocations.
+    pub allocs: usize,
+    /// Number of frees.
+    pub frees: usize,
+    /// Number of blocks in the free list.
+    pub free_blocks: usize,
+    /// The largest free block, the biggest allocation that can still succeed.
+    pub largest_free: usize,
+}
+
+struct HeapAllocatorInner {
+    /// Dummy list head, its `next` is the free block with the lowest address.
+    head: FreeBlock,
+    stats: HeapStats,
+
+    #[cfg(feature = "debug_heap")]
+    debug: debug::HeapDebug,
+}
+
+/// The kernel's heap allocator.
+pub struct HeapAllocator {
+    inner: IRQSafeSpinLock<HeapAllocatorInner>,
+}
+
+#[global_allocator]
+static KERNEL_HEAP_ALLOCATOR: HeapAllocator = HeapAllocator::new();
+
+/// Return a reference to the kernel's heap allocator.
+pub fn kernel_heap_allocator() -> &'static HeapAllocator {
+    &KERNEL_HEAP_ALLOCATOR
+}
+
+#[inline(always)]
+const fn align_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// The size and alignment a block for `layout` gets.
+fn block_size_align(layout: Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(MIN_BLOCK_SIZE), BLOCK_ALIGN);
+    let align = layout.align().max(BLOCK_ALIGN);
+
+    (size, align)
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!(
+        "Allocation error: {:?}\n      Heap: {}",
+        layout,
+        kernel_heap_allocator().stats()
+    )
+}
+
+// The raw pointers only ever point into the heap, which is accessed under the lock.
+unsafe impl Send for HeapAllocatorInner {}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} KiB used (peak {} KiB), {} allocs, {} frees, {} free blocks, largest {} KiB",
+            self.used / 1024,
+            self.size / 1024,
+            self.peak / 1024,
+            self.allocs,
+            self.frees,
+            self.free_blocks,
+            self.largest_free / 1024
+        )
+    }
+}
+
+impl HeapStats {
+    const fn new() -> Self {
+        Self {
+            size: 0,
+            used: 0,
+            peak: 0,
+            allocs: 0,
+            frees: 0,
+            free_blocks: 0,
+            largest_free: 0,
+        }
+    }
+}
+
+impl HeapAllocatorInner {
+    const fn new() -> Self {
+        Self {
+            head: FreeBlock {
+                size: 0,
+                next: ptr::null_mut(),
+            },
+            stats: HeapStats::new(),
+
+            #[cfg(feature = "debug_heap")]
+            debug: debug::HeapDebug::new(),
+        }
+    }
+
+    /// Put `[addr, addr + size)` back on the free list, merging it with adjacent free blocks.
+    ///
+    /// # Safety
+    ///
+    /// - The range must be unused heap memory, aligned to and a multiple of `BLOCK_ALIGN`.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        // find the blocks before and after the region
+        let mut prev: *mut FreeBlock = &mut self.head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < addr {
+            prev = (*prev).next;
+        }
+        let next = (*prev).next;
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        (*prev).next = block;
+
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev != &mut self.head as *mut _ && prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        }
+    }
+
+    unsafe fn init(&mut self, start: usize, size: usize) -> Result<(), &'static str> {
+        if self.stats.size != 0 {
+            return Err("Heap already initialized");
+        }
+
+        let aligned_start = align_up(start, BLOCK_ALIGN);
+        let size = (size - (aligned_start - start)) & !(BLOCK_ALIGN - 1);
+        if size < MIN_BLOCK_SIZE {
+            return Err("Heap region too small");
+        }
+
+        self.add_free_region(aligned_start, size);
+        self.stats.size = size;
+
+        Ok(())
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_size_align(layout);
+
+        // The heap sits at the top of the address space, the math below would wrap. Nothing that
+        // big fits anyway.
+        if size > self.stats.size || align > self.stats.size {
+            return ptr::null_mut();
+        }
+
+        let mut prev: *mut FreeBlock = &mut self.head;
+        while !(*prev).next.is_null() {
+            let block = (*prev).next;
+            let block_start = block as usize;
+            let block_end = block_start + (*block).size;
+
+            // the space in front of the allocation must be either nothing or a whole free block
+            let mut alloc_start = align_up(block_start, align);
+            if alloc_start != block_start && alloc_start - block_start < MIN_BLOCK_SIZE {
+                alloc_start = align_up(block_start + MIN_BLOCK_SIZE, align);
+            }
+            let alloc_end = alloc_start.checked_add(size);
+
+            // same for the space behind it
+            let fits = alloc_end.map_or(false, |alloc_end| {
+                alloc_end <= block_end
+                    && (alloc_end == block_end || block_end - alloc_end >= MIN_BLOCK_SIZE)
+            });
+            if !fits {
+                prev = block;
+                continue;
+            }
+
+            let alloc_end = alloc_start + size;
+
+            (*prev).next = (*block).next;
+            if alloc_start > block_start {
+                self.add_free_region(block_start, alloc_start - block_start);
+            }
+            if block_end > alloc_end {
+                self.add_free_region(alloc_end, block_end - alloc_end);
+            }
+
+            self.stats.used += size;
+            self.stats.peak = self.stats.peak.max(self.stats.used);
+            self.stats.allocs += 1;
+
+            return alloc_start as *mut u8;
+        }
+
+        ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_size_align(layout);
+
+        self.add_free_region(ptr as usize, size);
+
+        self.stats.used -= size;
+        self.stats.frees += 1;
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = self.stats;
+
+        let mut block = self.head.next;
+        while !block.is_null() {
+            unsafe {
+                stats.free_blocks += 1;
+                stats.largest_free = stats.largest_free.max((*block).size);
+                block = (*block).next;
+            }
+        }
+
+        stats
+    }
+}
+
+impl HeapAllocator {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeSpinLock::new(HeapAllocatorInner::new()),
+        }
+    }
+
+    /// Return the current usage counters.
+    pub fn stats(&self) -> HeapStats {
+        self.inner.lock(|inner| inner.stats())
+    }
+
+    /// Print the usage counters.
+    pub fn print_stats(&self) {
+        info!("      {}", self.stats());
+    }
+}
+
+#[cfg(not(feature = "debug_heap"))]
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.inner.lock(|inner| inner.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.lock(|inner| inner.dealloc(ptr, layout))
+    }
+}
+
+#[cfg(feature = "debug_heap")]
+unsafe impl GlobalAlloc for HeapAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let callers = debug::Callers::collect();
+
+        self.inner
+            .lock(|inner| inner.alloc_tracked(layout, callers))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(x) = self.inner.lock(|inner| inner.dealloc_tracked(ptr, layout)) {
+            panic!("Heap: {}", x);
+        }
+    }
+}
+
+/// Print the kernel heap's usage counters.
+pub fn print_heap_stats() {
+    kernel_heap_allocator().print_stats();
+}
+
+/// Mark the current point in time. `print_leaks()` with the returned value lists the allocations
+/// made afterwards that are still alive.
+#[cfg(feature = "debug_heap")]
+pub fn leak_checkpoint() -> usize {
+    kernel_heap_allocator()
+        .inner
+        .lock(|inner| inner.leak_checkpoint())
+}
+
+/// Print the live allocations made since `checkpoint`, with their callers. 0 lists all of them.
+#[cfg(feature = "debug_heap")]
+pub fn print_leaks(checkpoint: usize) {
+    debug::print_leaks(kernel_heap_allocator(), checkpoint)
+}
+
+/// Hand the `BSP`'s heap region to the kernel's heap allocator.
+pub fn kernel_init_heap_allocator() -> Result<(), &'static str> {
+    let region = bsp::memory::heap_range_inclusive();
+    let size = region.end() - region.start() + 1;
+
+    unsafe {
+        kernel_heap_allocator()
+            .inner
+            .lock(|inner| inner.init(*region.start(), size))
+    }
+}
+
+use synchronization::interface::Mutex;
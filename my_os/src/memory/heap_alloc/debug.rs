@@ -0,0 +1,301 @@
+//! Heap debugging, enabled with the `debug_heap` feature (`make DEBUG_HEAP=1`).
+//!
+//! - Every allocation sits between two red zones filled with a known pattern. They are checked when
+//!   the allocation is freed, freed memory gets poisoned.
+//! - Every live allocation is recorded with the return addresses of its callers. Resolve them with
+//!   `addr2line -e target/aarch64-unknown-none-softfloat/release/kernel <addr>`.
+//! - Frees of memory that was never handed out, and double frees, panic.
+//!
+//! Nothing panics or prints with the heap lock held, the panic handler and the consoles may well
+//! want to allocate.
+
+use super::{align_up, HeapAllocator, HeapAllocatorInner};
+use crate::{bsp, cpu, info, synchronization};
+use alloc::alloc::Layout;
+use core::{fmt, ptr};
+
+const RED_ZONE_SIZE: usize = 16;
+const RED_ZONE_BYTE: u8 = 0xFD;
+/// Red zone of an allocation that didn't fit in the records. Tells it apart from a double free.
+const RED_ZONE_UNTRACKED_BYTE: u8 = 0xFC;
+const FREED_BYTE: u8 = 0xDD;
+
+const MAX_TRACKED: usize = 1024;
+const NUM_CALLERS: usize = 4;
+
+/// Return addresses of an allocation's call chain, innermost first. Unused slots are 0.
+#[derive(Copy, Clone)]
+pub struct Callers([usize; NUM_CALLERS]);
+
+#[derive(Copy, Clone)]
+struct AllocRecord {
+    /// The address handed out, 0 if the slot is free.
+    addr: usize,
+    size: usize,
+    /// Allocation number, for telling what was allocated after a checkpoint.
+    seq: usize,
+    callers: Callers,
+}
+
+/// What was wrong with a free.
+pub enum FreeError {
+    /// Not allocated, or already freed.
+    NotAllocated { addr: usize, size: usize },
+    /// Something wrote past either end of the allocation.
+    RedZone {
+        addr: usize,
+        size: usize,
+        front: bool,
+        callers: Callers,
+    },
+}
+
+/// Live allocation records.
+pub struct HeapDebug {
+    records: [AllocRecord; MAX_TRACKED],
+    /// Live allocations that didn't fit in `records`.
+    untracked: usize,
+    next_seq: usize,
+}
+
+impl Callers {
+    /// Collect the return addresses of the current call chain, skipping the allocator itself.
+    #[inline(always)]
+    pub fn collect() -> Self {
+        const SKIP: usize = 1;
+
+        // only this core's stack is mapped for sure, a bogus frame pointer into a guard would fault
+        let stack = bsp::memory::core_stack_range_inclusive(cpu::smp::core_id());
+
+        let mut addrs = [0; NUM_CALLERS + SKIP];
+        cpu::return_addresses(stack, &mut addrs);
+
+        let mut callers = [0; NUM_CALLERS];
+        callers.copy_from_slice(&addrs[SKIP..]);
+
+        Self(callers)
+    }
+}
+
+impl fmt::Display for Callers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut addrs = self.0.iter().take_while(|addr| **addr != 0);
+
+        match addrs.next() {
+            None => write!(f, "unknown caller"),
+            Some(first) => {
+                write!(f, "{:#x}", first)?;
+                for addr in addrs {
+                    write!(f, " <- {:#x}", addr)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for FreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAllocated { addr, size } => write!(
+                f,
+                "free of {:#x} ({} bytes), which is not allocated. Double free?",
+                addr, size
+            ),
+            Self::RedZone {
+                addr,
+                size,
+                front,
+                callers,
+            } => write!(
+                f,
+                "red zone {} {:#x} ({} bytes) overwritten, allocated from {}",
+                if *front { "in front of" } else { "behind" },
+                addr,
+                size,
+                callers
+            ),
+        }
+    }
+}
+
+impl AllocRecord {
+    const EMPTY: Self = Self {
+        addr: 0,
+        size: 0,
+        seq: 0,
+        callers: Callers([0; NUM_CALLERS]),
+    };
+}
+
+impl HeapDebug {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            records: [AllocRecord::EMPTY; MAX_TRACKED],
+            untracked: 0,
+            next_seq: 1,
+        }
+    }
+
+    /// Returns false if there was no free slot for the record.
+    fn record(&mut self, addr: usize, size: usize, callers: Callers) -> bool {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        match self.records.iter_mut().find(|r| r.addr == 0) {
+            Some(slot) => {
+                *slot = AllocRecord {
+                    addr,
+                    size,
+                    seq,
+                    callers,
+                };
+
+                true
+            }
+            None => {
+                self.untracked += 1;
+
+                false
+            }
+        }
+    }
+
+    /// Remove and return the record of `addr`.
+    fn forget(&mut self, addr: usize) -> Option<AllocRecord> {
+        let slot = self.records.iter_mut().find(|r| r.addr == addr)?;
+        let record = *slot;
+        *slot = AllocRecord::EMPTY;
+
+        Some(record)
+    }
+}
+
+/// The red zone in front of an allocation is at least `RED_ZONE_SIZE`, and big enough to keep the
+/// allocation aligned.
+fn front_size(layout: Layout) -> usize {
+    align_up(RED_ZONE_SIZE, layout.align())
+}
+
+fn outer_layout(layout: Layout) -> Option<Layout> {
+    let size = front_size(layout) + layout.size() + RED_ZONE_SIZE;
+
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+unsafe fn red_zone_intact(start: usize, size: usize, byte: u8) -> bool {
+    (start..start + size).all(|addr| *(addr as *const u8) == byte)
+}
+
+impl HeapAllocatorInner {
+    pub(super) unsafe fn alloc_tracked(&mut self, layout: Layout, callers: Callers) -> *mut u8 {
+        let outer = match outer_layout(layout) {
+            Some(outer) => outer,
+            None => return ptr::null_mut(),
+        };
+
+        let base = self.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+
+        let front = front_size(layout);
+        let addr = base.add(front);
+        let size = layout.size();
+
+        // an untracked allocation is only known by its red zones
+        let red_zone_byte = if self.debug.record(addr as usize, size, callers) {
+            RED_ZONE_BYTE
+        } else {
+            RED_ZONE_UNTRACKED_BYTE
+        };
+        ptr::write_bytes(base, red_zone_byte, front);
+        ptr::write_bytes(addr.add(size), red_zone_byte, RED_ZONE_SIZE);
+
+        addr
+    }
+
+    pub(super) unsafe fn dealloc_tracked(
+        &mut self,
+        addr: *mut u8,
+        layout: Layout,
+    ) -> Result<(), FreeError> {
+        let front = front_size(layout);
+        let base = addr.sub(front);
+        let size = layout.size();
+        let back = addr as usize + size;
+
+        // Freed memory is poisoned red zones and all, so a second free of an untracked allocation
+        // doesn't find its red zone anymore. Unless the memory got handed out again in between.
+        let (callers, red_zone_byte) = match self.debug.forget(addr as usize) {
+            Some(record) => (record.callers, RED_ZONE_BYTE),
+            None if red_zone_intact(base as usize, front, RED_ZONE_UNTRACKED_BYTE) => {
+                self.debug.untracked -= 1;
+                (Callers([0; NUM_CALLERS]), RED_ZONE_UNTRACKED_BYTE)
+            }
+            None => {
+                return Err(FreeError::NotAllocated {
+                    addr: addr as usize,
+                    size,
+                })
+            }
+        };
+
+        for (in_front, start, len) in [(true, base as usize, front), (false, back, RED_ZONE_SIZE)] {
+            if !red_zone_intact(start, len, red_zone_byte) {
+                return Err(FreeError::RedZone {
+                    addr: addr as usize,
+                    size,
+                    front: in_front,
+                    callers,
+                });
+            }
+        }
+
+        // outer_layout() already worked for the allocation
+        if let Some(outer) = outer_layout(layout) {
+            ptr::write_bytes(base, FREED_BYTE, outer.size());
+            self.dealloc(base, outer);
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn leak_checkpoint(&self) -> usize {
+        self.debug.next_seq
+    }
+}
+
+/// Print the live allocations made since `since`, with their callers.
+///
+/// The records are copied out one at a time, the lock isn't held while printing.
+pub(super) fn print_leaks(heap: &HeapAllocator, since: usize) {
+    let mut count = 0;
+    let mut bytes = 0;
+
+    for index in 0..MAX_TRACKED {
+        let record = heap.inner.lock(|inner| inner.debug.records[index]);
+        if record.addr == 0 || record.seq < since {
+            continue;
+        }
+
+        info!(
+            "      #{: <6} {:#018x} {: >8} bytes, from {}",
+            record.seq, record.addr, record.size, record.callers
+        );
+
+        count += 1;
+        bytes += record.size;
+    }
+
+    info!("      {} allocations, {} bytes", count, bytes);
+
+    let untracked = heap.inner.lock(|inner| inner.debug.untracked);
+    if untracked != 0 {
+        info!("      {} more live allocations were not tracked", untracked);
+    }
+}
+
+use synchronization::interface::Mutex;
@@ -0,0 +1,328 @@
+//! Memory Management Unit.
+//!
+//! In order to decouple `BSP` and `arch` parts of the MMU code (to keep them pluggable), this file
+//! provides types for composing an architecture-agnostic description of the kernel's virtual
+//! memory layout.
+//!
+//! The `BSP` provides such a description through the `bsp::memory::mmu::virt_mem_layout()`
+//! function.
+//!
+//! The `MMU` driver of the `arch` code uses `bsp::memory::mmu::virt_mem_layout()` to compile and
+//! install respective translation tables.
+//!
+//! The kernel runs in the higher half of the address space (TTBR1 on aarch64). The early boot code
+//! switches the MMU on with coarse boot tables that map all of physical memory there, the kernel
+//! then replaces them with the fine-grained tables from the `BSP` layout. The lower half (TTBR0) is
+//! left unused, for future user address spaces.
+//!
+//! Device memory is not part of the static layout. Drivers map their registers at init with
+//! `kernel_map_mmio()`, which hands out virtual addresses from a window the `BSP` sets aside.
+
+#[path = "../_arch/aarch64/memory/mmu.rs"]
+mod arch_mmu;
+
+mod mapping_record;
+mod translation_table;
+
+use core::{fmt, ops::RangeInclusive};
+
+pub use arch_mmu::{enable_mmu_and_caching_early, mmu};
+
+/// MMU enable errors variants.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub enum MMUEnableError {
+    AlreadyEnabled,
+    Other(&'static str),
+}
+
+/// Memory Management interfaces.
+pub mod interface {
+    use super::*;
+
+    /// MMU functions.
+    pub trait MMU {
+        /// Called by the kernel during early init, on every core. Supposed to take the translation
+        /// tables from the `BSP`-supplied `virt_mem_layout()` and install them in place of the boot
+        /// tables the MMU was enabled with.
+        ///
+        /// # Safety
+        ///
+        /// - Changes the HW's global state.
+        unsafe fn install_kernel_tables(&self) -> Result<(), MMUEnableError>;
+
+        /// Returns true if the MMU is enabled, false otherwise.
+        fn is_enabled(&self) -> bool;
+    }
+}
+
+/// Describes the characteristics of a translation granule.
+pub struct TranslationGranule<const GRANULE_SIZE: usize>;
+
+/// Describes properties of an address space.
+pub struct AddressSpace<const AS_SIZE: usize>;
+
+/// Architecture agnostic translation types.
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum Translation {
+    /// The physical address is the virtual address minus the start of the layout's address space.
+    Linear,
+    /// The range is backed by physical memory starting at the given address.
+    Offset(usize),
+    /// The range is not mapped at all, every access faults. For guard pages.
+    Unmapped,
+}
+
+/// Architecture agnostic memory attributes.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum MemAttributes {
+    CacheableDRAM,
+    /// Normal memory that bypasses the caches. Writes may be gathered, unlike with `Device`.
+    NonCacheableDRAM,
+    Device,
+}
+
+/// Architecture agnostic access permissions.
+#[allow(missing_docs)]
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+pub enum AccessPermissions {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Collection of memory attributes.
+#[allow(missing_docs)]
+#[derive(Copy, Clone)]
+pub struct AttributeFields {
+    pub mem_attributes: MemAttributes,
+    pub acc_perms: AccessPermissions,
+    pub execute_never: bool,
+}
+
+/// Architecture agnostic descriptor for a memory range.
+#[allow(missing_docs)]
+pub struct TranslationDescriptor {
+    pub name: &'static str,
+    pub virtual_range: fn() -> RangeInclusive<usize>,
+    pub physical_range_translation: Translation,
+    pub attribute_fields: AttributeFields,
+}
+
+/// Type for expressing the kernel's virtual memory layout.
+pub struct KernelVirtualLayout<const NUM_SPECIAL_RANGES: usize> {
+    /// The first address of the address space.
+    virt_start: usize,
+
+    /// The last (inclusive) address of the address space.
+    max_virt_addr_inclusive: usize,
+
+    /// Array of descriptors for non-standard (normal cacheable DRAM) memory regions.
+    inner: [TranslationDescriptor; NUM_SPECIAL_RANGES],
+}
+
+impl fmt::Display for MMUEnableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MMUEnableError::AlreadyEnabled => write!(f, "MMU is already enabled"),
+            MMUEnableError::Other(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl<const GRANULE_SIZE: usize> TranslationGranule<GRANULE_SIZE> {
+    /// The granule's size.
+    pub const SIZE: usize = Self::size_checked();
+
+    /// The granule's mask.
+    pub const MASK: usize = Self::SIZE - 1;
+
+    /// The granule's shift, aka log2(size).
+    pub const SHIFT: usize = Self::SIZE.trailing_zeros() as usize;
+
+    const fn size_checked() -> usize {
+        assert!(GRANULE_SIZE.is_power_of_two());
+
+        GRANULE_SIZE
+    }
+}
+
+impl<const AS_SIZE: usize> AddressSpace<AS_SIZE> {
+    /// The address space size.
+    pub const SIZE: usize = Self::size_checked();
+
+    /// The address space shift, aka log2(size).
+    pub const SIZE_SHIFT: usize = Self::SIZE.trailing_zeros() as usize;
+
+    const fn size_checked() -> usize {
+        assert!(AS_SIZE.is_power_of_two());
+
+        // Check for architectural restrictions as well.
+        Self::arch_address_space_size_sanity_checks();
+
+        AS_SIZE
+    }
+}
+
+impl Default for AttributeFields {
+    fn default() -> AttributeFields {
+        AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+        }
+    }
+}
+
+/// Human-readable output of AttributeFields
+impl fmt::Display for AttributeFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attr = match self.mem_attributes {
+            MemAttributes::CacheableDRAM => "C",
+            MemAttributes::NonCacheableDRAM => "NC",
+            MemAttributes::Device => "Dev",
+        };
+
+        let acc_p = match self.acc_perms {
+            AccessPermissions::ReadOnly => "RO",
+            AccessPermissions::ReadWrite => "RW",
+        };
+
+        let xn = if self.execute_never { "PXN" } else { "PX" };
+
+        write!(f, "{: <3} {} {: <3}", attr, acc_p, xn)
+    }
+}
+
+/// Human-readable output of a TranslationDescriptor.
+impl fmt::Display for TranslationDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Call the function to which self.range points, and dereference the result, which causes
+        // Rust to copy the value.
+        let start = *(self.virtual_range)().start();
+        let end = *(self.virtual_range)().end();
+        let size = end - start + 1;
+
+        // log2(1024).
+        const KIB_RSHIFT: u32 = 10;
+
+        // log2(1024 * 1024).
+        const MIB_RSHIFT: u32 = 20;
+
+        let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
+            (size >> MIB_RSHIFT, "MiB")
+        } else if (size >> KIB_RSHIFT) > 0 {
+            (size >> KIB_RSHIFT, "KiB")
+        } else {
+            (size, "Byte")
+        };
+
+        write!(
+            f,
+            "      {:#018x} - {:#018x} | {: >3} {} | ",
+            start, end, size, unit
+        )?;
+
+        match self.physical_range_translation {
+            Translation::Unmapped => write!(f, "{: <10}", "unmapped")?,
+            _ => write!(f, "{}", self.attribute_fields)?,
+        }
+
+        write!(f, " | {}", self.name)
+    }
+}
+
+impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }> {
+    /// Create a new instance.
+    pub const fn new(
+        start: usize,
+        max: usize,
+        layout: [TranslationDescriptor; NUM_SPECIAL_RANGES],
+    ) -> Self {
+        Self {
+            virt_start: start,
+            max_virt_addr_inclusive: max,
+            inner: layout,
+        }
+    }
+
+    /// For a virtual address, find and return the physical output address and corresponding
+    /// attributes, or `None` if the address is to be left unmapped.
+    ///
+    /// The first descriptor in `inner` that covers the address wins. If there is none, return a
+    /// linearly mapped default with normal cacheable DRAM attributes.
+    pub fn virt_addr_properties(
+        &self,
+        virt_addr: usize,
+    ) -> Result<Option<(usize, AttributeFields)>, &'static str> {
+        if virt_addr < self.virt_start || virt_addr > self.max_virt_addr_inclusive {
+            return Err("Address out of range");
+        }
+
+        for i in self.inner.iter() {
+            if (i.virtual_range)().contains(&virt_addr) {
+                let output_addr = match i.physical_range_translation {
+                    Translation::Linear => virt_addr - self.virt_start,
+                    Translation::Offset(a) => a + (virt_addr - (i.virtual_range)().start()),
+                    Translation::Unmapped => return Ok(None),
+                };
+
+                return Ok(Some((output_addr, i.attribute_fields)));
+            }
+        }
+
+        Ok(Some((
+            virt_addr - self.virt_start,
+            AttributeFields::default(),
+        )))
+    }
+
+    /// Print the memory layout.
+    pub fn print_layout(&self) {
+        use crate::info;
+
+        for i in self.inner.iter() {
+            info!("{}", i);
+        }
+    }
+}
+
+/// Map the device memory at the physical `phys_range` into the kernel's MMIO remap window and
+/// return the virtual address its first byte ended up at. `name` shows up in the table of MMIO
+/// mappings.
+///
+/// Mapping a range that is already mapped, e.g. from a second driver using the same page, returns
+/// the existing mapping.
+pub fn kernel_map_mmio(
+    name: &'static str,
+    phys_range: &RangeInclusive<usize>,
+) -> Result<usize, &'static str> {
+    mapping_record::kernel_mapping_record().map_mmio(name, phys_range, MemAttributes::Device)
+}
+
+/// Like `kernel_map_mmio()`, but for memory that gets written in bulk and isn't registers, like a
+/// framebuffer. Mapped normal non-cacheable, so writes are gathered and plain memory copies work.
+pub fn kernel_map_mmio_write_combining(
+    name: &'static str,
+    phys_range: &RangeInclusive<usize>,
+) -> Result<usize, &'static str> {
+    mapping_record::kernel_mapping_record().map_mmio(
+        name,
+        phys_range,
+        MemAttributes::NonCacheableDRAM,
+    )
+}
+
+/// Take the DRAM at the physical `phys_range` out of the kernel's linear map, for memory the kernel
+/// must not touch through a cacheable mapping, like what the VideoCore keeps for itself. The range
+/// has to be page aligned.
+pub fn kernel_unmap_dram(phys_range: &RangeInclusive<usize>) -> Result<(), &'static str> {
+    mapping_record::kernel_mapping_record().unmap_dram(phys_range)
+}
+
+/// Print the table of active MMIO mappings.
+pub fn print_mmio_mappings() {
+    mapping_record::kernel_mapping_record().print()
+}
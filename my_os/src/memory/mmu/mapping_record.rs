@@ -0,0 +1,252 @@
+//! Record of the device memory mapped into the kernel's MMIO remap window.
+//!
+//! The window is handed out front to back and nothing is ever unmapped. Drivers whose registers
+//! share a page share the mapping as well, as long as they want the same memory attributes.
+//!
+//! DRAM that is not the kernel's to map cacheable gets taken out of the linear map through here as
+//! well, so that all changes to the live tables are serialized by the same lock.
+
+use super::{arch_mmu, AccessPermissions, AttributeFields, MemAttributes};
+use crate::{bsp, info, memory, synchronization, synchronization::IRQSafeSpinLock};
+use core::{fmt, ops::RangeInclusive};
+
+type KernelGranule = bsp::memory::mmu::KernelGranule;
+
+const MAX_MAPPINGS: usize = 12;
+const MAX_USERS: usize = 4;
+
+/// One contiguous mapping in the window.
+#[derive(Copy, Clone)]
+struct MMIOMapping {
+    users: [Option<&'static str>; MAX_USERS],
+    phys_start: usize,
+    virt_start: usize,
+    num_pages: usize,
+    attributes: AttributeFields,
+}
+
+/// Counts what gets written, for lining up the rows of a table.
+struct WidthCounter(usize);
+
+struct MappingRecordInner {
+    mappings: [Option<MMIOMapping>; MAX_MAPPINGS],
+
+    /// The first unused address of the window.
+    next_virt: usize,
+}
+
+/// The MMIO mappings made so far.
+pub struct MappingRecord {
+    inner: IRQSafeSpinLock<MappingRecordInner>,
+}
+
+static KERNEL_MAPPING_RECORD: MappingRecord = MappingRecord::new();
+
+/// Return a reference to the kernel's mapping record.
+pub fn kernel_mapping_record() -> &'static MappingRecord {
+    &KERNEL_MAPPING_RECORD
+}
+
+impl MMIOMapping {
+    fn size(&self) -> usize {
+        self.num_pages << KernelGranule::SHIFT
+    }
+
+    fn phys_range_inclusive(&self) -> RangeInclusive<usize> {
+        RangeInclusive::new(self.phys_start, self.phys_start + self.size() - 1)
+    }
+
+    fn add_user(&mut self, name: &'static str) -> Result<(), &'static str> {
+        if self.users.contains(&Some(name)) {
+            return Ok(());
+        }
+
+        let slot = self
+            .users
+            .iter_mut()
+            .find(|user| user.is_none())
+            .ok_or("Too many users of an MMIO mapping")?;
+        *slot = Some(name);
+
+        Ok(())
+    }
+}
+
+impl fmt::Write for WidthCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.chars().count();
+
+        Ok(())
+    }
+}
+
+// the address and size columns of a table row
+impl fmt::Display for MMIOMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = self.size();
+
+        write!(
+            f,
+            "{:#010x} - {:#010x} --> {:#018x} - {:#018x} | {: >4} KiB | {}",
+            self.phys_start,
+            self.phys_start + size - 1,
+            self.virt_start,
+            self.virt_start + size - 1,
+            size >> 10,
+            self.attributes
+        )
+    }
+}
+
+impl MappingRecordInner {
+    const fn new() -> Self {
+        Self {
+            mappings: [None; MAX_MAPPINGS],
+            next_virt: *bsp::memory::mmu::MMIO_REMAP_RANGE_INCLUSIVE.start(),
+        }
+    }
+
+    /// The existing mapping that covers all of the given physical pages, with `mem_attributes`.
+    fn find(
+        &mut self,
+        phys_start: usize,
+        num_pages: usize,
+        mem_attributes: MemAttributes,
+    ) -> Option<&mut MMIOMapping> {
+        let phys_end = phys_start + (num_pages << KernelGranule::SHIFT) - 1;
+
+        self.mappings.iter_mut().flatten().find(|mapping| {
+            let range = mapping.phys_range_inclusive();
+
+            mapping.attributes.mem_attributes == mem_attributes
+                && range.contains(&phys_start)
+                && range.contains(&phys_end)
+        })
+    }
+
+    fn map_mmio(
+        &mut self,
+        name: &'static str,
+        phys_range: &RangeInclusive<usize>,
+        mem_attributes: MemAttributes,
+    ) -> Result<usize, &'static str> {
+        if phys_range.is_empty() {
+            return Err("Empty MMIO range");
+        }
+
+        let phys_start = *phys_range.start() & !KernelGranule::MASK;
+        let offset = *phys_range.start() - phys_start;
+        let num_pages = ((*phys_range.end() - phys_start) >> KernelGranule::SHIFT) + 1;
+
+        if let Some(mapping) = self.find(phys_start, num_pages, mem_attributes) {
+            mapping.add_user(name)?;
+
+            return Ok(mapping.virt_start + (phys_start - mapping.phys_start) + offset);
+        }
+
+        let slot = self
+            .mappings
+            .iter()
+            .position(|mapping| mapping.is_none())
+            .ok_or("Too many MMIO mappings")?;
+
+        // the window may end at the very top of the address space, so mind the overflows
+        let window = bsp::memory::mmu::MMIO_REMAP_RANGE_INCLUSIVE;
+        let virt_start = self.next_virt;
+        let size = num_pages << KernelGranule::SHIFT;
+        if virt_start < *window.start() || *window.end() - virt_start < size - 1 {
+            return Err("MMIO remap window exhausted");
+        }
+
+        let attributes = AttributeFields {
+            mem_attributes,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+        };
+
+        unsafe {
+            arch_mmu::kernel_map_pages(virt_start, phys_start, num_pages, &attributes)?;
+        }
+
+        let mut users = [None; MAX_USERS];
+        users[0] = Some(name);
+        self.mappings[slot] = Some(MMIOMapping {
+            users,
+            phys_start,
+            virt_start,
+            num_pages,
+            attributes,
+        });
+
+        // wraps to 0 once the window is used up to the very end, the check above catches that
+        self.next_virt = virt_start.wrapping_add(size);
+
+        Ok(virt_start + offset)
+    }
+
+    fn unmap_dram(&mut self, phys_range: &RangeInclusive<usize>) -> Result<(), &'static str> {
+        let (phys_start, phys_end_exclusive) = (*phys_range.start(), *phys_range.end() + 1);
+        if phys_range.is_empty()
+            || phys_start & KernelGranule::MASK != 0
+            || phys_end_exclusive & KernelGranule::MASK != 0
+        {
+            return Err("DRAM range to unmap not page aligned");
+        }
+
+        if phys_end_exclusive > bsp::memory::MAX_DRAM_END_EXCLUSIVE {
+            return Err("DRAM range to unmap past the linear map");
+        }
+
+        let num_pages = (phys_end_exclusive - phys_start) >> KernelGranule::SHIFT;
+
+        unsafe { arch_mmu::kernel_unmap_pages(memory::phys_to_virt(phys_start), num_pages) }
+    }
+
+    fn print(&self) {
+        for mapping in self.mappings.iter().flatten() {
+            info!("      {} | {}", mapping, mapping.users[0].unwrap_or(""));
+
+            // further users go below the first, behind the same prefix and indentation
+            let mut width = WidthCounter(0);
+            let _ = fmt::write(&mut width, format_args!("{}", mapping));
+
+            for user in mapping.users.iter().skip(1).flatten() {
+                info!("      {: <width$} | {}", "", user, width = width.0);
+            }
+        }
+    }
+}
+
+impl MappingRecord {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeSpinLock::new(MappingRecordInner::new()),
+        }
+    }
+
+    /// Map `phys_range` with `mem_attributes` into the MMIO remap window, on behalf of `name`.
+    /// Returns the virtual address of the range's first byte.
+    pub fn map_mmio(
+        &self,
+        name: &'static str,
+        phys_range: &RangeInclusive<usize>,
+        mem_attributes: MemAttributes,
+    ) -> Result<usize, &'static str> {
+        // the lock also serializes the changes to the translation tables
+        self.inner
+            .lock(|inner| inner.map_mmio(name, phys_range, mem_attributes))
+    }
+
+    /// Take `phys_range` out of the kernel's linear map of DRAM.
+    pub fn unmap_dram(&self, phys_range: &RangeInclusive<usize>) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.unmap_dram(phys_range))
+    }
+
+    /// Print all mappings with the drivers using them.
+    pub fn print(&self) {
+        self.inner.lock(|inner| inner.print())
+    }
+}
+
+use synchronization::interface::Mutex;
@@ -0,0 +1,6 @@
+//! Translation table.
+
+#[path = "../../_arch/aarch64/memory/mmu/translation_table.rs"]
+mod arch_translation_table;
+
+pub use arch_translation_table::{BootTranslationTable, KernelTranslationTable};
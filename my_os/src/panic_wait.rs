@@ -1,6 +1,6 @@
 //! A panic handler that infinitely waits.
 
-use crate::{cpu, println};
+use crate::{bsp, cpu};
 use core::panic::PanicInfo;
 
 fn panic_prevent_reenter() {
@@ -29,17 +29,21 @@ fn panic(info: &PanicInfo) -> ! {
         _ => ("???", 0, 0),
     };
 
-    println!(
-        "[  {:>3}.{:06}] Kernel panic!\n\n\
-        Panic location:\n      File '{}', line {}, column {}\n\n\
-        {}",
-        timestamp.as_secs(),
-        timestamp.subsec_micros(),
-        location,
-        line,
-        column,
-        info.message().unwrap_or(&format_args!("")),
-    );
+    // The normal console path takes locks, which the code we interrupted may well be holding on
+    // this very core. Go straight to the UART instead, there is no one left to report to anyway.
+    let _ = unsafe {
+        bsp::drivers::panic_console_write_fmt(format_args_nl!(
+            "[  {:>3}.{:06}] Kernel panic!\n\n\
+            Panic location:\n      File '{}', line {}, column {}\n\n\
+            {}",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            location,
+            line,
+            column,
+            info.message().unwrap_or(&format_args!("")),
+        ))
+    };
 
     cpu::wait_forever()
 }
@@ -1,6 +1,10 @@
 // ! The mutex
 
-use core::cell::UnsafeCell;
+use crate::{exception, state};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 // synchronization interface (for the hardware)
 pub mod interface {
@@ -8,14 +12,133 @@ pub mod interface {
         type Data; // type of data for the mutex
         fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R; // lock the mutex
     }
+
+    /// A reader-writer exclusion type.
+    ///
+    /// The implementing object allows either a number of readers or at most one writer at any
+    /// point in time.
+    pub trait ReadWriteEx {
+        /// The type of encapsulated data.
+        type Data;
+
+        /// Grants temporary mutable access to the encapsulated data.
+        fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R;
+
+        /// Grants temporary immutable access to the encapsulated data.
+        fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R;
+    }
+}
+
+// The exclusive load/store pair (LDAXR/STLXR) behind the atomic read-modify-write ops only works on
+// cacheable memory, so it can not be used before the MMU is on. Until then the locks take their
+// ticket with a plain load and store, which is only correct while the boot core runs alone.
+static EXCLUSIVES_READY: AtomicBool = AtomicBool::new(false);
+
+/// Switch the locks over to real atomics.
+///
+/// # Safety
+///
+/// - The MMU and data caches must be on for every core that runs from here on.
+pub unsafe fn enable_exclusives() {
+    EXCLUSIVES_READY.store(true, Ordering::Release);
+}
+
+// Atomic read-modify-write of `atomic`, same contract as `AtomicUsize::fetch_update()`. Falls back
+// to a plain load and store with IRQs masked while exclusives are not usable yet.
+fn fetch_update(
+    atomic: &AtomicUsize,
+    mut f: impl FnMut(usize) -> Option<usize>,
+) -> Result<usize, usize> {
+    if EXCLUSIVES_READY.load(Ordering::Acquire) {
+        return atomic.fetch_update(Ordering::AcqRel, Ordering::Acquire, f);
+    }
+
+    exception::asynchronous::exec_with_irq_masked(|| {
+        let current = atomic.load(Ordering::Acquire);
+
+        match f(current) {
+            None => Err(current),
+            Some(new) => {
+                atomic.store(new, Ordering::Release);
+                Ok(current)
+            }
+        }
+    })
+}
+
+// the ticket lock behind both lock types. Cores are served in the order they asked for the lock.
+struct RawSpinLock {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+
+    #[cfg(feature = "debug_locks")]
+    owner: deadlock::Owner,
 }
 
-// only works when kernel is executing single-threaded, aka only running on a single core with
-// interrupts disabled
+impl RawSpinLock {
+    const fn new() -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+
+            #[cfg(feature = "debug_locks")]
+            owner: deadlock::Owner::new(),
+        }
+    }
+
+    fn take_ticket(&self) -> usize {
+        if EXCLUSIVES_READY.load(Ordering::Acquire) {
+            return self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // single core, but an IRQ could still take a ticket between the load and the store
+        fetch_update(&self.next_ticket, |ticket| Some(ticket.wrapping_add(1))).unwrap()
+    }
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn acquire(&self) {
+        #[cfg(feature = "debug_locks")]
+        self.owner.check_reentry();
+
+        let ticket = self.take_ticket();
+
+        #[cfg(feature = "debug_locks")]
+        let mut spins: usize = 0;
+
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            #[cfg(feature = "debug_locks")]
+            {
+                spins += 1;
+                if spins == deadlock::SPIN_LIMIT {
+                    self.owner.report_stuck();
+                }
+            }
+
+            core::hint::spin_loop();
+        }
+
+        #[cfg(feature = "debug_locks")]
+        self.owner.set();
+    }
+
+    fn release(&self) {
+        #[cfg(feature = "debug_locks")]
+        self.owner.clear();
+
+        // only the holder ever writes now_serving, so no read-modify-write needed
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.now_serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
+
+// a real spinlock. Must not be used for data that is also touched from IRQ context, an IRQ taking
+// the lock while the interrupted code holds it spins forever. Use IRQSafeSpinLock for that.
 pub struct SpinLock<T>
 where
     T: ?Sized,
 {
+    raw: RawSpinLock,
     data: UnsafeCell<T>,
 }
 
@@ -25,6 +148,7 @@ unsafe impl<T> Sync for SpinLock<T> where T: ?Sized + Send {}
 impl<T> SpinLock<T> {
     pub const fn new(data: T) -> Self {
         Self {
+            raw: RawSpinLock::new(),
             data: UnsafeCell::new(data),
         }
     }
@@ -33,10 +157,319 @@ impl<T> SpinLock<T> {
 impl<T> interface::Mutex for SpinLock<T> {
     type Data = T;
 
+    #[cfg_attr(feature = "debug_locks", track_caller)]
     fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        self.raw.acquire();
+
         // mutable reference will ever only be given out once at a time.
         let data = unsafe { &mut *self.data.get() }; // get pointer to item
+        let ret = f(data);
+
+        self.raw.release();
+
+        ret // return the function's result from the data
+    }
+}
+
+// same as SpinLock, but IRQs are masked on the executing core while the lock is held. use this for
+// data that is also touched from IRQ context
+pub struct IRQSafeSpinLock<T>
+where
+    T: ?Sized,
+{
+    raw: RawSpinLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for IRQSafeSpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for IRQSafeSpinLock<T> where T: ?Sized + Send {}
+
+impl<T> IRQSafeSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            raw: RawSpinLock::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> interface::Mutex for IRQSafeSpinLock<T> {
+    type Data = T;
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        // mask first, so no IRQ can come in and spin on the lock we are holding
+        let saved = unsafe { exception::asynchronous::local_irq_mask_save() };
+        self.raw.acquire();
+
+        // mutable reference will ever only be given out once at a time.
+        let data = unsafe { &mut *self.data.get() };
+        let ret = f(data);
+
+        self.raw.release();
+        unsafe { exception::asynchronous::local_irq_restore(saved) };
+
+        ret
+    }
+}
+
+// Lets many readers in at once, or a single writer. A waiting writer keeps new readers out, so a
+// steady stream of readers can not starve it. IRQs are masked while the lock is held, so it can be
+// used for data that is also touched from IRQ context.
+pub struct RwSpinLock<T>
+where
+    T: ?Sized,
+{
+    // reader count, plus the WRITER and WRITER_WAITING bits
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for RwSpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for RwSpinLock<T> where T: ?Sized + Send + Sync {}
+
+impl<T> RwSpinLock<T> {
+    const WRITER: usize = 1 << (usize::BITS - 1);
+    const WRITER_WAITING: usize = 1 << (usize::BITS - 2);
+
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn acquire_read(&self) {
+        #[cfg(feature = "debug_locks")]
+        let mut spins: usize = 0;
+
+        // one more reader, unless a writer holds the lock or waits for it
+        let add_reader = |state: usize| {
+            if state & (Self::WRITER | Self::WRITER_WAITING) != 0 {
+                return None;
+            }
+
+            Some(state + 1)
+        };
+
+        while fetch_update(&self.state, add_reader).is_err() {
+            #[cfg(feature = "debug_locks")]
+            {
+                spins += 1;
+                if spins == deadlock::SPIN_LIMIT {
+                    deadlock::report_stuck_rw("read");
+                }
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn acquire_write(&self) {
+        #[cfg(feature = "debug_locks")]
+        let mut spins: usize = 0;
+
+        loop {
+            // take the lock if it is free, otherwise flag that a writer is waiting
+            let previous = fetch_update(&self.state, |state| {
+                if state & !Self::WRITER_WAITING == 0 {
+                    Some(Self::WRITER)
+                } else {
+                    Some(state | Self::WRITER_WAITING)
+                }
+            })
+            .unwrap();
+
+            if previous & !Self::WRITER_WAITING == 0 {
+                return;
+            }
+
+            #[cfg(feature = "debug_locks")]
+            {
+                spins += 1;
+                if spins == deadlock::SPIN_LIMIT {
+                    deadlock::report_stuck_rw("write");
+                }
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl<T> interface::ReadWriteEx for RwSpinLock<T> {
+    type Data = T;
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        let saved = unsafe { exception::asynchronous::local_irq_mask_save() };
+        self.acquire_write();
+
+        // the WRITER bit keeps everybody else out
+        let data = unsafe { &mut *self.data.get() };
+        let ret = f(data);
+
+        // other writers may have set WRITER_WAITING in the meantime, keep it
+        fetch_update(&self.state, |state| Some(state & !Self::WRITER)).unwrap();
+        unsafe { exception::asynchronous::local_irq_restore(saved) };
+
+        ret
+    }
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R {
+        let saved = unsafe { exception::asynchronous::local_irq_mask_save() };
+        self.acquire_read();
+
+        let data = unsafe { &*self.data.get() };
+        let ret = f(data);
+
+        fetch_update(&self.state, |state| Some(state - 1)).unwrap();
+        unsafe { exception::asynchronous::local_irq_restore(saved) };
+
+        ret
+    }
+}
+
+// Only allows writes while the kernel is still in its init phase, where a single core runs with
+// IRQs masked. After that the data is read-only, so reads need no locking at all.
+pub struct InitStateLock<T>
+where
+    T: ?Sized,
+{
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Send for InitStateLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for InitStateLock<T> where T: ?Sized + Send + Sync {}
+
+impl<T> InitStateLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> interface::ReadWriteEx for InitStateLock<T> {
+    type Data = T;
+
+    #[cfg_attr(feature = "debug_locks", track_caller)]
+    fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        assert!(
+            state::state_manager().is_init(),
+            "InitStateLock::write called after kernel init phase"
+        );
+        assert!(
+            exception::asynchronous::is_local_irq_masked(),
+            "InitStateLock::write called with IRQs unmasked"
+        );
+
+        let data = unsafe { &mut *self.data.get() };
+
+        f(data)
+    }
+
+    fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R {
+        let data = unsafe { &*self.data.get() };
+
+        f(data)
+    }
+}
+
+// Only with the `debug_locks` feature. Remembers which core holds a lock and where it was taken, so
+// a core taking a lock twice (e.g. from an IRQ handler) or spinning for way too long panics with
+// something useful instead of hanging silently.
+#[cfg(feature = "debug_locks")]
+mod deadlock {
+    use super::*;
+    use crate::cpu;
+    use core::{panic::Location, ptr, sync::atomic::AtomicPtr};
+
+    // roughly a few seconds of spinning
+    pub const SPIN_LIMIT: usize = 1 << 28;
+
+    const NO_CORE: usize = usize::MAX;
+
+    pub struct Owner {
+        core: AtomicUsize,
+        location: AtomicPtr<Location<'static>>,
+    }
+
+    impl Owner {
+        pub const fn new() -> Self {
+            Self {
+                core: AtomicUsize::new(NO_CORE),
+                location: AtomicPtr::new(ptr::null_mut()),
+            }
+        }
+
+        fn location(&self) -> &'static Location<'static> {
+            let location = self.location.load(Ordering::Relaxed);
+
+            if location.is_null() {
+                Location::caller()
+            } else {
+                unsafe { &*location }
+            }
+        }
+
+        #[track_caller]
+        pub fn check_reentry(&self) {
+            let core: usize = cpu::smp::core_id();
+
+            if self.core.load(Ordering::Relaxed) == core {
+                panic!(
+                    "Deadlock: core {} took a spinlock it already holds\n      \
+                    Taken at: {}\n      Held since: {}",
+                    core,
+                    Location::caller(),
+                    self.location()
+                );
+            }
+        }
+
+        #[track_caller]
+        pub fn report_stuck(&self) {
+            let core: usize = cpu::smp::core_id();
+
+            panic!(
+                "Possible deadlock: core {} stuck waiting for a spinlock\n      \
+                Taken at: {}\n      Held by core {} since: {}",
+                core,
+                Location::caller(),
+                self.core.load(Ordering::Relaxed),
+                self.location()
+            );
+        }
+
+        #[track_caller]
+        pub fn set(&self) {
+            let location = Location::caller() as *const Location<'static>;
+
+            self.location.store(location as *mut _, Ordering::Relaxed);
+            self.core.store(cpu::smp::core_id(), Ordering::Relaxed);
+        }
+
+        pub fn clear(&self) {
+            self.core.store(NO_CORE, Ordering::Relaxed);
+            self.location.store(ptr::null_mut(), Ordering::Relaxed);
+        }
+    }
+
+    // a RwSpinLock does not track its holders, so only say who is stuck
+    #[track_caller]
+    pub fn report_stuck_rw(access: &str) {
+        let core: usize = cpu::smp::core_id();
 
-        f(data) // return the function's result from the data
+        panic!(
+            "Possible deadlock: core {} stuck waiting for {} access to a RwSpinLock\n      \
+            Taken at: {}",
+            core,
+            access,
+            Location::caller()
+        );
     }
 }
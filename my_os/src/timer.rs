@@ -1,11 +1,78 @@
 //! Timer
+//!
+//! Besides telling the time, the `TimeManager` runs callbacks once a timeout expires. Pending
+//! timeouts are kept in a queue sorted by due time, the architectural timer is armed for the first
+//! of them and its IRQ runs whatever is due.
+//!
+//! For measuring time there is `Instant`, and `Deadline` for loops that wait for something but
+//! should give up at some point.
+//!
+//! Other clocks in the system, such as the `BSP`'s, register as `interface::ClockSource` and get
+//! checked against the architectural counter, whose frequency nothing else vouches for.
+//!
+//! The timer IRQ is a per core interrupt and only the boot core takes it, so timeouts can only be
+//! set from there.
 
 #[path = "_arch/aarch64/timer.rs"]
 mod arch_time;
 
-use core::time::Duration;
+use crate::{
+    bsp, cpu, drivers, exception, info,
+    synchronization::{self, IRQSafeSpinLock, InitStateLock},
+    warn,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{num::NonZeroU64, time::Duration};
 
-pub struct TimeManager;
+/// Timer interfaces.
+pub mod interface {
+    use super::*;
+
+    /// A free-running counter.
+    pub trait ClockSource {
+        /// Name for printing.
+        fn name(&self) -> &'static str;
+
+        /// Ticks per second.
+        fn frequency(&self) -> NonZeroU64;
+
+        /// The current counter value.
+        fn ticks(&self) -> u64;
+    }
+}
+
+type ClockSources = Vec<&'static (dyn interface::ClockSource + Sync)>;
+
+/// A point in time, as read from the architectural counter. Never goes backwards.
+#[derive(Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
+pub struct Instant(arch_time::GenericTimerCounterValue);
+
+/// The point in time by which a wait has to be over.
+#[derive(Copy, Clone)]
+pub struct Deadline {
+    instant: Instant,
+}
+
+/// Called when a timeout expires. Runs in IRQ context.
+pub type TimeoutCallback = Box<dyn Fn() + Send>;
+
+struct Timeout {
+    due_time: Duration,
+    period: Option<Duration>,
+    callback: TimeoutCallback,
+}
+
+/// Pending timeouts, latest first, so that the next one to expire sits at the end.
+struct TimeoutQueue {
+    inner: Vec<Timeout>,
+}
+
+pub struct TimeManager {
+    queue: IRQSafeSpinLock<TimeoutQueue>,
+
+    /// Registered during kernel init, RO afterwards.
+    clock_sources: InitStateLock<ClockSources>,
+}
 
 /// Global instance
 static TIME_MANAGER: TimeManager = TimeManager::new();
@@ -15,9 +82,111 @@ pub fn time_manager() -> &'static TimeManager {
     &TIME_MANAGER
 }
 
+impl Instant {
+    /// So far in the future that it is never reached.
+    const FAR_FUTURE: Self = Self(arch_time::GenericTimerCounterValue::MAX);
+
+    /// The current point in time.
+    pub fn now() -> Self {
+        Self(arch_time::read_cntpct())
+    }
+
+    /// The time that passed since `self`.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    /// The time from `earlier` to `self`, zero if `earlier` is actually later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0).into()
+    }
+
+    /// `self` plus `duration`, `None` if the counter can't represent it.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let ticks = arch_time::GenericTimerCounterValue::try_from(duration).ok()?;
+
+        self.0.checked_add(ticks).map(Self)
+    }
+}
+
+impl Deadline {
+    /// The deadline `timeout` from now. One too far out to be represented is never reached.
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            instant: Instant::now()
+                .checked_add(timeout)
+                .unwrap_or(Instant::FAR_FUTURE),
+        }
+    }
+
+    pub fn has_passed(&self) -> bool {
+        Instant::now() >= self.instant
+    }
+
+    /// The time left until the deadline, zero once it has passed.
+    #[allow(dead_code)]
+    pub fn remaining(&self) -> Duration {
+        self.instant.duration_since(Instant::now())
+    }
+
+    /// Spin until `condition` holds. Gives up once the deadline has passed, but not without
+    /// checking `condition` one last time.
+    pub fn poll_until(&self, mut condition: impl FnMut() -> bool) -> Result<(), &'static str> {
+        loop {
+            let passed = self.has_passed();
+
+            if condition() {
+                return Ok(());
+            }
+
+            if passed {
+                return Err("Deadline passed");
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl TimeoutQueue {
+    /// Room for this many timeouts is made at init, so that the queue doesn't allocate in IRQ
+    /// context in the common case.
+    const INITIAL_CAPACITY: usize = 16;
+
+    const fn new() -> Self {
+        Self { inner: Vec::new() }
+    }
+
+    fn push(&mut self, timeout: Timeout) {
+        // in front of the ones due at the same time, so those still expire first
+        let index = self
+            .inner
+            .partition_point(|t| t.due_time > timeout.due_time);
+
+        self.inner.insert(index, timeout);
+    }
+
+    /// Take the next timeout if it is due at `now`.
+    fn pop_due(&mut self, now: Duration) -> Option<Timeout> {
+        match self.inner.last() {
+            Some(timeout) if timeout.due_time <= now => self.inner.pop(),
+            _ => None,
+        }
+    }
+
+    fn next_due_time(&self) -> Option<Duration> {
+        self.inner.last().map(|timeout| timeout.due_time)
+    }
+}
+
 impl TimeManager {
+    pub const COMPATIBLE: &'static str = "ARM Architectural Timer";
+
     pub const fn new() -> Self {
-        Self
+        Self {
+            queue: IRQSafeSpinLock::new(TimeoutQueue::new()),
+            clock_sources: InitStateLock::new(Vec::new()),
+        }
     }
 
     pub fn resolution(&self) -> Duration {
@@ -30,6 +199,173 @@ impl TimeManager {
     }
 
     pub fn spin_for(&self, duration: Duration) {
-        arch_time::spin_for(duration)
+        let deadline = match Instant::now().checked_add(duration) {
+            None => {
+                warn!("spin_for: Duration too big. Skipping");
+                return;
+            }
+            Some(instant) => Deadline { instant },
+        };
+
+        while !deadline.has_passed() {}
+    }
+
+    /// Add a clock to check the architectural counter against.
+    pub fn register_clock_source(&self, source: &'static (dyn interface::ClockSource + Sync)) {
+        self.clock_sources.write(|sources| sources.push(source));
+    }
+
+    /// Print the clock sources, and what they make of `CNTFRQ_EL0`: each one is read before and
+    /// after a short spin to see how fast the architectural counter really runs.
+    pub fn cross_check_clock_sources(&self) {
+        const MEASURE_TIME: Duration = Duration::from_millis(20);
+        const MAX_DEVIATION_PERCENT: u64 = 1;
+
+        let arch_freq = arch_time::frequency().get();
+        info!(
+            "      {}: {} Hz according to CNTFRQ_EL0",
+            Self::COMPATIBLE,
+            arch_freq
+        );
+
+        self.clock_sources.read(|sources| {
+            for source in sources.iter() {
+                let start = Instant::now();
+                let source_start = source.ticks();
+                self.spin_for(MEASURE_TIME);
+                let source_ticks = source.ticks().wrapping_sub(source_start);
+                let elapsed = start.elapsed();
+
+                if source_ticks == 0 {
+                    warn!("{} is not counting", source.name());
+                    continue;
+                }
+
+                // the architectural ticks that passed, over the time that passed by the source
+                let arch_ticks = elapsed.as_nanos() * u128::from(arch_freq) / 1_000_000_000;
+                let measured_freq = (arch_ticks * u128::from(source.frequency().get())
+                    / u128::from(source_ticks)) as u64;
+
+                info!(
+                    "      {}: {} Hz, measures CNTFRQ_EL0 as {} Hz",
+                    source.name(),
+                    source.frequency(),
+                    measured_freq
+                );
+
+                if measured_freq.abs_diff(arch_freq) * 100 > arch_freq * MAX_DEVIATION_PERCENT {
+                    warn!(
+                        "CNTFRQ_EL0 is off by more than {}% from {}",
+                        MAX_DEVIATION_PERCENT,
+                        source.name()
+                    );
+                }
+            }
+        });
+    }
+
+    /// Run `callback` once, `delay` from now.
+    pub fn set_timeout_once(&self, delay: Duration, callback: TimeoutCallback) {
+        self.set_timeout(Timeout {
+            due_time: self.uptime() + delay,
+            period: None,
+            callback,
+        });
+    }
+
+    /// Run `callback` every `period`, starting `period` from now.
+    #[allow(dead_code)]
+    pub fn set_timeout_periodic(&self, period: Duration, callback: TimeoutCallback) {
+        if period < self.resolution() {
+            warn!("set_timeout_periodic: Period below the timer resolution. Skipping");
+            return;
+        }
+
+        self.set_timeout(Timeout {
+            due_time: self.uptime() + period,
+            period: Some(period),
+            callback,
+        });
+    }
+
+    fn set_timeout(&self, timeout: Timeout) {
+        if cpu::smp::core_id::<u64>() != bsp::cpu::BOOT_CORE_ID {
+            warn!("set_timeout: Timer IRQs only reach the boot core. Skipping");
+            return;
+        }
+
+        self.queue.lock(|queue| {
+            queue.push(timeout);
+
+            if let Some(due_time) = queue.next_due_time() {
+                arch_time::set_timeout_irq(due_time);
+            }
+        });
+    }
+}
+
+//* for the OS
+
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl drivers::interface::DeviceDriver for TimeManager {
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.queue
+            .lock(|queue| queue.inner.reserve(TimeoutQueue::INITIAL_CAPACITY));
+
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &exception::asynchronous::IRQNumber,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for TimeManager {
+    fn handle(&self) -> Result<(), &'static str> {
+        arch_time::conclude_timeout_irq();
+
+        // the lock is not held while a callback runs, so callbacks can set timeouts themselves
+        loop {
+            let now = self.uptime();
+            let timeout = match self.queue.lock(|queue| queue.pop_due(now)) {
+                None => break,
+                Some(timeout) => timeout,
+            };
+
+            (timeout.callback)();
+
+            if let Some(period) = timeout.period {
+                // relative to when it was due, not to now, so that periodic timeouts don't drift
+                self.queue.lock(|queue| {
+                    queue.push(Timeout {
+                        due_time: timeout.due_time + period,
+                        ..timeout
+                    })
+                });
+            }
+        }
+
+        self.queue.lock(|queue| {
+            if let Some(due_time) = queue.next_due_time() {
+                arch_time::set_timeout_irq(due_time);
+            }
+        });
+
+        Ok(())
     }
 }